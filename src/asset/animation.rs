@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+
+use indexmap::IndexMap;
+
+use super::AssetReader;
+use super::SBType;
+use super::frames::{self, Frames};
+use super::path;
+
+#[derive(Debug, Clone)]
+pub struct State {
+    pub frames: u32,
+    pub cycle: f64,
+    // "end", "loop" or "transition"
+    pub mode: String,
+    pub transition: Option<String>,
+    pub properties: IndexMap<String, SBType>,
+    pub frame_properties: IndexMap<String, SBType>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StateType {
+    pub default: Option<String>,
+    pub priority: f64,
+    pub states: IndexMap<String, State>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PartState {
+    pub properties: IndexMap<String, SBType>,
+    pub frame_properties: IndexMap<String, SBType>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub properties: IndexMap<String, SBType>,
+    // state type -> state -> overrides
+    pub part_states: IndexMap<String, IndexMap<String, PartState>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub path: String,
+    pub state_types: IndexMap<String, StateType>,
+    pub parts: IndexMap<String, Part>,
+    pub transformation_groups: IndexMap<String, SBType>,
+    pub sounds: IndexMap<String, Vec<String>>,
+    pub particle_emitters: IndexMap<String, SBType>,
+    pub global_tags: IndexMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+fn object(value: Option<&SBType>) -> IndexMap<String, SBType> {
+    match value {
+        Some(SBType::Object(map)) => map.clone(),
+        _ => IndexMap::new(),
+    }
+}
+
+fn strings(value: Option<&SBType>) -> IndexMap<String, String> {
+    object(value)
+        .into_iter()
+        .filter_map(|(key, value)| value.as_str().map(|value| (key, value.to_string())))
+        .collect()
+}
+
+impl Animation {
+    pub fn parse(animation_path: &str, value: &SBType) -> anyhow::Result<Self> {
+        if !matches!(value, SBType::Object(_)) {
+            anyhow::bail!("Animation is not an object");
+        }
+        let animated_parts = value.get("animatedParts");
+
+        let mut state_types = IndexMap::new();
+        for (name, state_type) in object(animated_parts.and_then(|parts| parts.get("stateTypes"))) {
+            let mut states = IndexMap::new();
+            for (state_name, state) in object(state_type.get("states")) {
+                states.insert(
+                    state_name,
+                    State {
+                        frames: state
+                            .get("frames")
+                            .and_then(SBType::as_i64)
+                            .unwrap_or(1)
+                            .max(0) as u32,
+                        cycle: state.get("cycle").and_then(SBType::as_f64).unwrap_or(1.0),
+                        mode: state
+                            .get("mode")
+                            .and_then(SBType::as_str)
+                            .unwrap_or("end")
+                            .to_string(),
+                        transition: state
+                            .get("transition")
+                            .and_then(SBType::as_str)
+                            .map(str::to_string),
+                        properties: object(state.get("properties")),
+                        frame_properties: object(state.get("frameProperties")),
+                    },
+                );
+            }
+
+            state_types.insert(
+                name,
+                StateType {
+                    default: state_type
+                        .get("default")
+                        .and_then(SBType::as_str)
+                        .map(str::to_string),
+                    priority: state_type
+                        .get("priority")
+                        .and_then(SBType::as_f64)
+                        .unwrap_or(0.0),
+                    states,
+                },
+            );
+        }
+
+        let mut parts = IndexMap::new();
+        for (name, part) in object(animated_parts.and_then(|parts| parts.get("parts"))) {
+            let mut part_states = IndexMap::new();
+            for (state_type, states) in object(part.get("partStates")) {
+                let states = object(Some(&states))
+                    .into_iter()
+                    .map(|(state, overrides)| {
+                        (
+                            state,
+                            PartState {
+                                properties: object(overrides.get("properties")),
+                                frame_properties: object(overrides.get("frameProperties")),
+                            },
+                        )
+                    })
+                    .collect();
+                part_states.insert(state_type, states);
+            }
+
+            parts.insert(
+                name,
+                Part {
+                    properties: object(part.get("properties")),
+                    part_states,
+                },
+            );
+        }
+
+        // Sounds are either a list of files or an object with a pool of files
+        let sounds = object(value.get("sounds"))
+            .into_iter()
+            .map(|(name, sound)| {
+                let pool = match &sound {
+                    SBType::Object(_) => sound.get("pool").cloned(),
+                    _ => Some(sound),
+                };
+                let files = pool
+                    .as_ref()
+                    .and_then(SBType::as_array)
+                    .map(|files| {
+                        files
+                            .iter()
+                            .filter_map(SBType::as_str)
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                (name, files)
+            })
+            .collect();
+
+        Ok(Self {
+            path: animation_path.to_string(),
+            state_types,
+            parts,
+            transformation_groups: object(value.get("transformationGroups")),
+            sounds,
+            particle_emitters: object(value.get("particleEmitters")),
+            global_tags: strings(value.get("globalTagDefaults")),
+        })
+    }
+
+    pub(super) fn load(reader: &dyn AssetReader, animation_path: &str) -> anyhow::Result<Self> {
+        Self::parse(animation_path, &reader.file(animation_path)?.as_json()?)
+    }
+
+    pub(super) fn validate(&self, reader: &dyn AssetReader) -> Report {
+        let mut report = Report::default();
+        let mut frames_cache: HashMap<String, Option<Frames>> = HashMap::new();
+
+        for (type_name, state_type) in &self.state_types {
+            if let Some(default) = &state_type.default
+                && !state_type.states.contains_key(default)
+            {
+                report.errors.push(format!(
+                    "State type '{}' defaults to missing state '{}'",
+                    type_name, default
+                ));
+            }
+
+            for (state_name, state) in &state_type.states {
+                match (state.mode.as_str(), &state.transition) {
+                    ("transition", None) => report.errors.push(format!(
+                        "State '{}.{}' transitions without a target",
+                        type_name, state_name
+                    )),
+                    ("transition", Some(target)) if !state_type.states.contains_key(target) => {
+                        report.errors.push(format!(
+                            "State '{}.{}' transitions to missing state '{}'",
+                            type_name, state_name, target
+                        ))
+                    }
+                    ("transition", _) | ("end", _) | ("loop", _) => {}
+                    (mode, _) => report.warnings.push(format!(
+                        "State '{}.{}' has unknown mode '{}'",
+                        type_name, state_name, mode
+                    )),
+                }
+
+                for (key, values) in &state.frame_properties {
+                    if let SBType::Array(values) = values
+                        && values.len() < state.frames as usize
+                    {
+                        report.warnings.push(format!(
+                            "State '{}.{}' has {} frames but frameProperty '{}' only {}",
+                            type_name,
+                            state_name,
+                            state.frames,
+                            key,
+                            values.len()
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (part_name, part) in &self.parts {
+            let mut tags = self.global_tags.clone();
+            tags.extend(strings(part.properties.get("partTagDefaults")));
+
+            for (type_name, states) in &part.part_states {
+                let Some(state_type) = self.state_types.get(type_name) else {
+                    report.errors.push(format!(
+                        "Part '{}' uses missing state type '{}'",
+                        part_name, type_name
+                    ));
+                    continue;
+                };
+
+                for (state_name, part_state) in states {
+                    let Some(state) = state_type.states.get(state_name) else {
+                        report.errors.push(format!(
+                            "Part '{}' uses missing state '{}.{}'",
+                            part_name, type_name, state_name
+                        ));
+                        continue;
+                    };
+
+                    let image = part_state
+                        .properties
+                        .get("image")
+                        .or_else(|| part.properties.get("image"))
+                        .and_then(SBType::as_str);
+                    if let Some(image) = image {
+                        let context =
+                            format!("Part '{}' state '{}.{}'", part_name, type_name, state_name);
+                        self.validate_image(
+                            reader,
+                            &context,
+                            image,
+                            state.frames,
+                            &tags,
+                            &mut frames_cache,
+                            &mut report,
+                        );
+                    }
+                }
+            }
+
+            // Parts without state overrides draw their base image
+            if part.part_states.is_empty()
+                && let Some(image) = part.properties.get("image").and_then(SBType::as_str)
+            {
+                let context = format!("Part '{}'", part_name);
+                self.validate_image(
+                    reader,
+                    &context,
+                    image,
+                    1,
+                    &tags,
+                    &mut frames_cache,
+                    &mut report,
+                );
+            }
+        }
+
+        for (name, files) in &self.sounds {
+            for file in files {
+                let file = path::resolve(&self.path, file);
+                if !reader.exist(&file) {
+                    report.errors.push(format!(
+                        "Sound '{}' references missing file '{}'",
+                        name, file
+                    ));
+                }
+            }
+        }
+
+        report
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn validate_image(
+        &self,
+        reader: &dyn AssetReader,
+        context: &str,
+        image: &str,
+        frame_count: u32,
+        tags: &IndexMap<String, String>,
+        frames_cache: &mut HashMap<String, Option<Frames>>,
+        report: &mut Report,
+    ) {
+        // Directives don't affect which file or frame is used
+        let image = image.split('?').next().unwrap_or_default();
+        let mut resolved = image.to_string();
+        for (tag, value) in tags {
+            resolved = resolved.replace(&format!("<{}>", tag), value);
+        }
+
+        let (file, frame) = match resolved.split_once(':') {
+            Some((file, frame)) => (file.to_string(), Some(frame.to_string())),
+            None => (resolved.clone(), None),
+        };
+        if file.is_empty() {
+            return;
+        }
+        // Tags filled in by the object or item can't be checked here
+        if file.contains('<') {
+            report.warnings.push(format!(
+                "{} image '{}' uses tags that are set at runtime",
+                context, image
+            ));
+            return;
+        }
+
+        let file = path::resolve(&self.path, &file);
+        if !reader.exist(&file) {
+            report
+                .errors
+                .push(format!("{} references missing image '{}'", context, file));
+            return;
+        }
+
+        let Some(frame) = frame else {
+            return;
+        };
+        let frames =
+            frames_cache
+                .entry(file.clone())
+                .or_insert_with(|| match frames::load(reader, &file) {
+                    Ok(frames) => frames,
+                    Err(e) => {
+                        report
+                            .errors
+                            .push(format!("Failed to load frames for '{}': {}", file, e));
+                        None
+                    }
+                });
+        let Some(frames) = frames else {
+            report.errors.push(format!(
+                "{} uses frame '{}' but '{}' has no frames file",
+                context, frame, file
+            ));
+            return;
+        };
+
+        let names: Vec<String> = if frame.contains("<frame>") {
+            (1..=frame_count)
+                .map(|index| frame.replace("<frame>", &index.to_string()))
+                .collect()
+        } else {
+            vec![frame.clone()]
+        };
+        for name in names {
+            if name.contains('<') {
+                report.warnings.push(format!(
+                    "{} frame '{}' uses tags that are set at runtime",
+                    context, frame
+                ));
+                break;
+            }
+            if frames.frame(&name).is_none() {
+                report.errors.push(format!(
+                    "{} needs frame '{}' which '{}' does not define",
+                    context, name, file
+                ));
+            }
+        }
+    }
+}
+
+impl From<&Report> for SBType {
+    fn from(value: &Report) -> Self {
+        let strings = |strings: &Vec<String>| {
+            SBType::Array(strings.iter().cloned().map(SBType::String).collect())
+        };
+        SBType::Object(
+            [
+                ("errors".to_string(), strings(&value.errors)),
+                ("warnings".to_string(), strings(&value.warnings)),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
+impl From<&Animation> for SBType {
+    fn from(value: &Animation) -> Self {
+        let state_types = value
+            .state_types
+            .iter()
+            .map(|(name, state_type)| {
+                let states = state_type
+                    .states
+                    .iter()
+                    .map(|(name, state)| {
+                        let mut map = IndexMap::new();
+                        map.insert("frames".to_string(), SBType::Int(state.frames as i64));
+                        map.insert("cycle".to_string(), SBType::Float(state.cycle));
+                        map.insert("mode".to_string(), SBType::String(state.mode.clone()));
+                        if let Some(transition) = &state.transition {
+                            map.insert(
+                                "transition".to_string(),
+                                SBType::String(transition.clone()),
+                            );
+                        }
+                        map.insert(
+                            "properties".to_string(),
+                            SBType::Object(state.properties.clone()),
+                        );
+                        map.insert(
+                            "frameProperties".to_string(),
+                            SBType::Object(state.frame_properties.clone()),
+                        );
+                        (name.clone(), SBType::Object(map))
+                    })
+                    .collect();
+
+                let mut map = IndexMap::new();
+                if let Some(default) = &state_type.default {
+                    map.insert("default".to_string(), SBType::String(default.clone()));
+                }
+                map.insert("priority".to_string(), SBType::Float(state_type.priority));
+                map.insert("states".to_string(), SBType::Object(states));
+                (name.clone(), SBType::Object(map))
+            })
+            .collect();
+
+        let parts = value
+            .parts
+            .iter()
+            .map(|(name, part)| {
+                let part_states = part
+                    .part_states
+                    .iter()
+                    .map(|(state_type, states)| {
+                        let states = states
+                            .iter()
+                            .map(|(state, overrides)| {
+                                let mut map = IndexMap::new();
+                                map.insert(
+                                    "properties".to_string(),
+                                    SBType::Object(overrides.properties.clone()),
+                                );
+                                map.insert(
+                                    "frameProperties".to_string(),
+                                    SBType::Object(overrides.frame_properties.clone()),
+                                );
+                                (state.clone(), SBType::Object(map))
+                            })
+                            .collect();
+                        (state_type.clone(), SBType::Object(states))
+                    })
+                    .collect();
+
+                let mut map = IndexMap::new();
+                map.insert(
+                    "properties".to_string(),
+                    SBType::Object(part.properties.clone()),
+                );
+                map.insert("partStates".to_string(), SBType::Object(part_states));
+                (name.clone(), SBType::Object(map))
+            })
+            .collect();
+
+        let sounds = value
+            .sounds
+            .iter()
+            .map(|(name, files)| {
+                (
+                    name.clone(),
+                    SBType::Array(files.iter().cloned().map(SBType::String).collect()),
+                )
+            })
+            .collect();
+
+        SBType::Object(
+            [
+                ("path".to_string(), SBType::String(value.path.clone())),
+                ("stateTypes".to_string(), SBType::Object(state_types)),
+                ("parts".to_string(), SBType::Object(parts)),
+                (
+                    "transformationGroups".to_string(),
+                    SBType::Object(value.transformation_groups.clone()),
+                ),
+                ("sounds".to_string(), SBType::Object(sounds)),
+                (
+                    "particleEmitters".to_string(),
+                    SBType::Object(value.particle_emitters.clone()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
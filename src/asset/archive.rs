@@ -0,0 +1,194 @@
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::sync::Mutex;
+
+use indexmap::IndexMap;
+use zip::write::SimpleFileOptions;
+
+use super::AssetReader;
+use super::SBType;
+use super::file::AssetFile;
+
+const METADATA_FILES: [&str; 2] = ["_metadata", ".metadata"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl TryFrom<&str> for ArchiveFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "tar" => Ok(ArchiveFormat::Tar),
+            "zip" => Ok(ArchiveFormat::Zip),
+            _ => anyhow::bail!("Unsupported archive format '{}'", value),
+        }
+    }
+}
+
+// Writes every asset plus a `_metadata` file, in sorted order so repeated exports match
+pub fn export(
+    reader: &dyn AssetReader,
+    output: &str,
+    format: ArchiveFormat,
+) -> anyhow::Result<usize> {
+    let mut paths: Vec<String> = reader
+        .paths()
+        .into_iter()
+        .filter(|path| {
+            !METADATA_FILES
+                .iter()
+                .any(|name| path.trim_start_matches('/') == *name)
+        })
+        .cloned()
+        .collect();
+    paths.sort();
+
+    let metadata = match reader.metadata() {
+        SBType::Nil => None,
+        metadata => Some(super::jsonc::stringify(&metadata, Some(2))),
+    };
+
+    let file = fs::File::create(output)?;
+
+    match format {
+        ArchiveFormat::Zip => {
+            let mut writer = zip::ZipWriter::new(file);
+            let options =
+                SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+            if let Some(metadata) = &metadata {
+                writer.start_file(METADATA_FILES[0], options)?;
+                writer.write_all(metadata.as_bytes())?;
+            }
+            for path in &paths {
+                let asset = reader.file(path)?;
+                writer.start_file(path.trim_start_matches('/'), options)?;
+                writer.write_all(&asset.bytes)?;
+            }
+            writer.finish()?;
+        }
+        ArchiveFormat::Tar => {
+            let mut builder = tar::Builder::new(file);
+            let mut append = |name: &str, bytes: &[u8]| -> anyhow::Result<()> {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(bytes.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(0);
+                builder.append_data(&mut header, name, bytes)?;
+                Ok(())
+            };
+
+            if let Some(metadata) = &metadata {
+                append(METADATA_FILES[0], metadata.as_bytes())?;
+            }
+            for path in &paths {
+                let asset = reader.file(path)?;
+                append(path.trim_start_matches('/'), &asset.bytes)?;
+            }
+            builder.into_inner()?.flush()?;
+        }
+    }
+
+    Ok(paths.len())
+}
+
+pub struct ZipReader {
+    archive: Mutex<zip::ZipArchive<Cursor<Vec<u8>>>>,
+    // asset path -> entry name inside the archive
+    index: IndexMap<String, String>,
+    metadata: SBType,
+}
+
+impl ZipReader {
+    pub fn new(bytes: Vec<u8>) -> anyhow::Result<Self> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+
+        let names: Vec<String> = archive
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .map(str::to_string)
+            .collect();
+
+        // Mod releases are often zipped with a single top level folder around the assets
+        let root = names
+            .iter()
+            .filter_map(|name| {
+                let (prefix, file_name) = name.rsplit_once('/').unwrap_or(("", name));
+                (METADATA_FILES.contains(&file_name) && !prefix.contains('/'))
+                    .then(|| prefix.to_string())
+            })
+            .min_by_key(String::len)
+            .unwrap_or_default();
+        let root = if root.is_empty() {
+            root
+        } else {
+            format!("{}/", root)
+        };
+
+        let mut index = IndexMap::new();
+        let mut metadata = SBType::Nil;
+
+        for name in names {
+            let Some(relative) = name.strip_prefix(&root) else {
+                continue;
+            };
+            if METADATA_FILES.contains(&relative) {
+                let mut source = String::new();
+                archive.by_name(&name)?.read_to_string(&mut source)?;
+                metadata = super::jsonc::parse(&source)?;
+                continue;
+            }
+            index.insert(format!("/{}", relative), name);
+        }
+
+        Ok(Self {
+            archive: Mutex::new(archive),
+            index,
+            metadata,
+        })
+    }
+}
+
+impl AssetReader for ZipReader {
+    fn exist(&self, path: &str) -> bool {
+        self.index.contains_key(path)
+    }
+
+    fn file(&self, path: &str) -> anyhow::Result<AssetFile> {
+        let name = match self.index.get(path) {
+            Some(name) => name,
+            None => anyhow::bail!("File is not exist"),
+        };
+
+        let mut bytes = Vec::new();
+        self.archive
+            .lock()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?
+            .by_name(name)?
+            .read_to_end(&mut bytes)?;
+
+        Ok(AssetFile {
+            path: path.to_string(),
+            bytes,
+        })
+    }
+
+    fn paths(&self) -> Vec<&String> {
+        self.index.keys().collect()
+    }
+
+    fn meta(&self, key: String) -> anyhow::Result<SBType> {
+        match self.metadata.get(&key) {
+            Some(value) => Ok(value.clone()),
+            None => anyhow::bail!("Key '{}' not found in metadata", key),
+        }
+    }
+
+    fn metadata(&self) -> SBType {
+        self.metadata.clone()
+    }
+}
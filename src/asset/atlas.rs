@@ -0,0 +1,292 @@
+use std::fs;
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use super::SBType;
+use super::jsonc;
+use crate::utils::bitmap::Bitmap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackMode {
+    // Every frame gets a cell of the same size, written as a `frameGrid`
+    Grid,
+    // Frames are packed in shelves by height, written as a `frameList`
+    Pack,
+}
+
+impl TryFrom<&str> for PackMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "grid" => Ok(PackMode::Grid),
+            "pack" => Ok(PackMode::Pack),
+            _ => anyhow::bail!("Unsupported pack mode '{}'", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PackOptions {
+    pub mode: PackMode,
+    pub columns: Option<u32>,
+    pub padding: u32,
+    pub aliases: IndexMap<String, String>,
+}
+
+impl TryFrom<&SBType> for PackOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &SBType) -> Result<Self, Self::Error> {
+        let mut options = Self::default();
+        if let Some(mode) = value.get("mode").and_then(SBType::as_str) {
+            options.mode = PackMode::try_from(mode)?;
+        }
+        options.columns = value
+            .get("columns")
+            .and_then(SBType::as_i64)
+            .map(|columns| columns.max(1) as u32);
+        options.padding = value
+            .get("padding")
+            .and_then(SBType::as_i64)
+            .unwrap_or(0)
+            .max(0) as u32;
+        if let Some(SBType::Object(aliases)) = value.get("aliases") {
+            for (alias, target) in aliases {
+                match target.as_str() {
+                    Some(target) => {
+                        options.aliases.insert(alias.clone(), target.to_string());
+                    }
+                    None => anyhow::bail!("Alias '{}' must name a frame", alias),
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            mode: PackMode::Grid,
+            columns: None,
+            padding: 0,
+            aliases: IndexMap::new(),
+        }
+    }
+}
+
+pub struct Atlas {
+    pub image: Bitmap,
+    pub frames: SBType,
+}
+
+fn frame_name(path: &str) -> String {
+    let name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match name.strip_suffix(".png") {
+        Some(stem) => stem.to_string(),
+        None => name,
+    }
+}
+
+pub fn load_frames(frames: &SBType) -> anyhow::Result<Vec<(String, Bitmap)>> {
+    // Either a list of files named after their stem, or a table of name -> file
+    let files: Vec<(String, String)> = match frames {
+        SBType::Array(files) => files
+            .iter()
+            .map(|file| match file.as_str() {
+                Some(file) => Ok((frame_name(file), file.to_string())),
+                None => Err(anyhow::anyhow!("Frame paths must be strings")),
+            })
+            .collect::<anyhow::Result<_>>()?,
+        SBType::Object(files) => files
+            .iter()
+            .map(|(name, file)| match file.as_str() {
+                Some(file) => Ok((name.clone(), file.to_string())),
+                None => Err(anyhow::anyhow!("Frame '{}' path must be a string", name)),
+            })
+            .collect::<anyhow::Result<_>>()?,
+        _ => anyhow::bail!("Expected a table of frames"),
+    };
+
+    let mut loaded: Vec<(String, Bitmap)> = Vec::with_capacity(files.len());
+    for (name, file) in files {
+        if loaded.iter().any(|(other, _)| *other == name) {
+            anyhow::bail!("Duplicate frame name '{}'", name);
+        }
+        let image = Bitmap::from_png(&fs::read(&file)?)
+            .map_err(|e| anyhow::anyhow!("Failed to load frame '{}': {}", file, e))?;
+        loaded.push((name, image));
+    }
+    Ok(loaded)
+}
+
+fn pair(a: u32, b: u32) -> SBType {
+    SBType::Array(vec![SBType::Int(a as i64), SBType::Int(b as i64)])
+}
+
+fn pack_grid(frames: &[(String, Bitmap)], options: &PackOptions) -> (Bitmap, SBType) {
+    // Grids have no gap between cells, so the padding widens every cell instead
+    let padding = options.padding;
+    let width = frames.iter().map(|(_, f)| f.width).max().unwrap_or(0) + padding;
+    let height = frames.iter().map(|(_, f)| f.height).max().unwrap_or(0) + padding;
+    let count = frames.len() as u32;
+    let columns = options
+        .columns
+        .unwrap_or_else(|| (count as f64).sqrt().ceil() as u32)
+        .clamp(1, count.max(1));
+    let rows = count.div_ceil(columns);
+
+    let mut image = Bitmap::new(width * columns, height * rows);
+    let mut names = vec![vec![SBType::Nil; columns as usize]; rows as usize];
+    for (i, (name, frame)) in frames.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        // Smaller frames are centered, the game anchors sprites at the frame center
+        let x = column * width + (width - frame.width) / 2;
+        let y = row * height + (height - frame.height) / 2;
+        image.draw(frame, x as i64, y as i64);
+        names[row as usize][column as usize] = SBType::String(name.clone());
+    }
+
+    let mut grid = IndexMap::new();
+    grid.insert("size".to_string(), pair(width, height));
+    grid.insert("dimensions".to_string(), pair(columns, rows));
+    grid.insert(
+        "names".to_string(),
+        SBType::Array(names.into_iter().map(SBType::Array).collect()),
+    );
+
+    let mut spec = IndexMap::new();
+    spec.insert("frameGrid".to_string(), SBType::Object(grid));
+    (image, SBType::Object(spec))
+}
+
+fn pack_shelves(frames: &[(String, Bitmap)], options: &PackOptions) -> (Bitmap, SBType) {
+    let padding = options.padding;
+    let area: u64 = frames
+        .iter()
+        .map(|(_, f)| (f.width + padding) as u64 * (f.height + padding) as u64)
+        .sum();
+    let widest = frames.iter().map(|(_, f)| f.width).max().unwrap_or(0);
+    let target_width = ((area as f64).sqrt().ceil() as u32).max(widest);
+
+    // Tallest first keeps the shelves tight, ties keep the input order
+    let mut order: Vec<usize> = (0..frames.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(frames[i].1.height));
+
+    let mut rects = vec![[0u32; 4]; frames.len()];
+    let (mut x, mut y, mut shelf_height, mut width) = (0u32, 0u32, 0u32, 0u32);
+    for i in order {
+        let frame = &frames[i].1;
+        if x > 0 && x + frame.width > target_width {
+            x = 0;
+            y += shelf_height + padding;
+            shelf_height = 0;
+        }
+        rects[i] = [x, y, x + frame.width, y + frame.height];
+        x += frame.width + padding;
+        shelf_height = shelf_height.max(frame.height);
+        width = width.max(x - padding);
+    }
+    let height = y + shelf_height;
+
+    let mut image = Bitmap::new(width, height);
+    let mut list = IndexMap::new();
+    for ((name, frame), rect) in frames.iter().zip(&rects) {
+        image.draw(frame, rect[0] as i64, rect[1] as i64);
+        list.insert(
+            name.clone(),
+            SBType::Array(rect.iter().map(|&v| SBType::Int(v as i64)).collect()),
+        );
+    }
+
+    let mut spec = IndexMap::new();
+    spec.insert("frameList".to_string(), SBType::Object(list));
+    (image, SBType::Object(spec))
+}
+
+pub fn pack(frames: &[(String, Bitmap)], options: &PackOptions) -> anyhow::Result<Atlas> {
+    if frames.is_empty() {
+        anyhow::bail!("No frames to pack");
+    }
+    for (alias, target) in &options.aliases {
+        if !frames.iter().any(|(name, _)| name == target) {
+            anyhow::bail!("Alias '{}' points to missing frame '{}'", alias, target);
+        }
+        if frames.iter().any(|(name, _)| name == alias) {
+            anyhow::bail!("Alias '{}' has the same name as a frame", alias);
+        }
+    }
+
+    let (image, mut spec) = match options.mode {
+        PackMode::Grid => pack_grid(frames, options),
+        PackMode::Pack => pack_shelves(frames, options),
+    };
+
+    if !options.aliases.is_empty()
+        && let SBType::Object(spec) = &mut spec
+    {
+        let aliases = options
+            .aliases
+            .iter()
+            .map(|(alias, target)| (alias.clone(), SBType::String(target.clone())))
+            .collect();
+        spec.insert("aliases".to_string(), SBType::Object(aliases));
+    }
+
+    Ok(Atlas {
+        image,
+        frames: spec,
+    })
+}
+
+// Writes the sheet and its `.frames` file next to it
+pub fn write(atlas: &Atlas, output: &str) -> anyhow::Result<String> {
+    let frames_path = match output.strip_suffix(".png") {
+        Some(stem) => format!("{}.frames", stem),
+        None => format!("{}.frames", output),
+    };
+    fs::write(output, atlas.image.to_png()?)?;
+    fs::write(&frames_path, jsonc::stringify(&atlas.frames, Some(2)))?;
+    Ok(frames_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opaque(width: u32, height: u32) -> Bitmap {
+        let mut bitmap = Bitmap::new(width, height);
+        bitmap.data.fill(255);
+        bitmap
+    }
+
+    #[test]
+    fn grid_padding_separates_frames() {
+        let frames = vec![
+            ("a".to_string(), opaque(2, 2)),
+            ("b".to_string(), opaque(2, 2)),
+        ];
+        let options = PackOptions {
+            columns: Some(2),
+            padding: 2,
+            ..PackOptions::default()
+        };
+        let (image, spec) = pack_grid(&frames, &options);
+        assert_eq!((image.width, image.height), (8, 4));
+        let size: Vec<i64> = spec
+            .get("frameGrid")
+            .and_then(|grid| grid.get("size"))
+            .and_then(SBType::as_array)
+            .map(|size| size.iter().filter_map(SBType::as_i64).collect())
+            .unwrap_or_default();
+        assert_eq!(size, [4, 4]);
+        // Both frames sit centered in their cell, two transparent columns apart
+        let row: Vec<u8> = (0..8).map(|x| image.pixel(x, 1)[3]).collect();
+        assert_eq!(row, [0, 255, 255, 0, 0, 255, 255, 0]);
+    }
+}
@@ -0,0 +1,207 @@
+use byteorder::{ByteOrder, LittleEndian};
+use indexmap::IndexMap;
+
+use super::SBType;
+
+// The game's mixer runs at this rate, everything else gets played at the wrong speed
+const MIXER_SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug, Clone)]
+pub struct AudioInfo {
+    pub codec: String,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits: Option<u16>,
+    pub duration: Option<f64>,
+    pub issues: Vec<String>,
+}
+
+fn parse_wav(bytes: &[u8]) -> anyhow::Result<AudioInfo> {
+    let mut format = None;
+    let mut data_size = None;
+
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = LittleEndian::read_u32(&bytes[offset + 4..offset + 8]) as usize;
+        let body = &bytes[offset + 8..(offset + 8 + size).min(bytes.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => format = Some(body.to_vec()),
+            b"data" => data_size = Some(size),
+            _ => {}
+        }
+        // Chunks are padded to an even size
+        offset += 8 + size + (size & 1);
+    }
+
+    let Some(format) = format else {
+        anyhow::bail!("WAV file has no fmt chunk");
+    };
+    let mut tag = LittleEndian::read_u16(&format[0..2]);
+    let channels = LittleEndian::read_u16(&format[2..4]);
+    let sample_rate = LittleEndian::read_u32(&format[4..8]);
+    let byte_rate = LittleEndian::read_u32(&format[8..12]);
+    let bits = LittleEndian::read_u16(&format[14..16]);
+    // WAVE_FORMAT_EXTENSIBLE keeps the real format in the sub format GUID
+    if tag == 0xfffe && format.len() >= 26 {
+        tag = LittleEndian::read_u16(&format[24..26]);
+    }
+
+    let codec = match tag {
+        1 => "pcm".to_string(),
+        3 => "float".to_string(),
+        2 => "adpcm".to_string(),
+        6 => "alaw".to_string(),
+        7 => "mulaw".to_string(),
+        tag => format!("wav(0x{:04x})", tag),
+    };
+
+    let mut issues = Vec::new();
+    if tag != 1 {
+        issues.push(format!("Unsupported WAV codec '{}', use 16-bit PCM", codec));
+    } else if ![8, 16, 24, 32].contains(&bits) {
+        issues.push(format!("Unsupported PCM bit depth {}", bits));
+    }
+    if data_size.is_none() {
+        issues.push("WAV file has no data chunk".to_string());
+    }
+
+    Ok(AudioInfo {
+        codec,
+        channels,
+        sample_rate,
+        bits: Some(bits),
+        duration: data_size
+            .filter(|_| byte_rate > 0)
+            .map(|size| size as f64 / byte_rate as f64),
+        issues,
+    })
+}
+
+// Granule position of the last page, which is the stream length in samples
+fn last_granule(bytes: &[u8]) -> Option<u64> {
+    let mut end = bytes.len();
+    while end >= 14 {
+        let start = bytes[..end].windows(4).rposition(|w| w == b"OggS")?;
+        if start + 14 <= bytes.len() && bytes[start + 4] == 0 {
+            return Some(LittleEndian::read_u64(&bytes[start + 6..start + 14]));
+        }
+        end = start;
+    }
+    None
+}
+
+fn parse_ogg(bytes: &[u8]) -> anyhow::Result<AudioInfo> {
+    if bytes.len() < 27 {
+        anyhow::bail!("Ogg file is truncated");
+    }
+    // First packet starts right after the segment table of the first page
+    let segments = bytes[26] as usize;
+    let packet = bytes.get(27 + segments..).unwrap_or_default();
+    let granule = last_granule(bytes);
+
+    if packet.len() >= 16 && &packet[..7] == b"\x01vorbis" {
+        let channels = packet[11] as u16;
+        let sample_rate = LittleEndian::read_u32(&packet[12..16]);
+        return Ok(AudioInfo {
+            codec: "vorbis".to_string(),
+            channels,
+            sample_rate,
+            bits: None,
+            duration: granule
+                .filter(|_| sample_rate > 0)
+                .map(|granule| granule as f64 / sample_rate as f64),
+            issues: Vec::new(),
+        });
+    }
+
+    if packet.len() >= 16 && &packet[..8] == b"OpusHead" {
+        let channels = packet[9] as u16;
+        let pre_skip = LittleEndian::read_u16(&packet[10..12]) as u64;
+        // Opus always decodes at 48kHz, the header rate is only informational
+        return Ok(AudioInfo {
+            codec: "opus".to_string(),
+            channels,
+            sample_rate: 48000,
+            bits: None,
+            duration: granule.map(|granule| granule.saturating_sub(pre_skip) as f64 / 48000.0),
+            issues: vec!["Unsupported Ogg codec 'opus', use Vorbis".to_string()],
+        });
+    }
+
+    let codec = match packet.get(1..7) {
+        Some(b"theora") => "theora",
+        _ if packet.starts_with(b"Speex") => "speex",
+        _ if packet.starts_with(b"\x7fFLAC") => "flac",
+        _ => "unknown",
+    };
+    Ok(AudioInfo {
+        codec: codec.to_string(),
+        channels: 0,
+        sample_rate: 0,
+        bits: None,
+        duration: None,
+        issues: vec![format!("Unsupported Ogg codec '{}', use Vorbis", codec)],
+    })
+}
+
+// `music` decides which channel layout is expected, music tracks are stereo and
+// positional sound effects are mono
+pub fn inspect(bytes: &[u8], music: bool) -> anyhow::Result<AudioInfo> {
+    let mut info = if bytes.starts_with(b"OggS") {
+        parse_ogg(bytes)?
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WAVE" {
+        parse_wav(bytes)?
+    } else {
+        anyhow::bail!("Not an Ogg or WAV file");
+    };
+
+    if info.sample_rate != 0 && info.sample_rate != MIXER_SAMPLE_RATE {
+        info.issues.push(format!(
+            "Sample rate is {} Hz, the game plays audio at {} Hz",
+            info.sample_rate, MIXER_SAMPLE_RATE
+        ));
+    }
+    match (info.channels, music) {
+        (0, _) => {}
+        (1, true) => info
+            .issues
+            .push("Music is mono, tracks should be stereo".to_string()),
+        (2, false) => info
+            .issues
+            .push("Sound effect is stereo, it will not be positioned in the world".to_string()),
+        (1 | 2, _) => {}
+        (channels, _) => info
+            .issues
+            .push(format!("Unsupported channel count {}", channels)),
+    }
+
+    Ok(info)
+}
+
+pub fn is_music(path: &str) -> bool {
+    path.starts_with("/music/")
+}
+
+impl From<&AudioInfo> for SBType {
+    fn from(value: &AudioInfo) -> Self {
+        let mut map = IndexMap::new();
+        map.insert("codec".to_string(), SBType::String(value.codec.clone()));
+        map.insert("channels".to_string(), SBType::Int(value.channels as i64));
+        map.insert(
+            "sample_rate".to_string(),
+            SBType::Int(value.sample_rate as i64),
+        );
+        if let Some(bits) = value.bits {
+            map.insert("bits".to_string(), SBType::Int(bits as i64));
+        }
+        if let Some(duration) = value.duration {
+            map.insert("duration".to_string(), SBType::Float(duration));
+        }
+        map.insert(
+            "issues".to_string(),
+            SBType::Array(value.issues.iter().cloned().map(SBType::String).collect()),
+        );
+        SBType::Object(map)
+    }
+}
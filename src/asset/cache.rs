@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::SystemTime;
+
+use super::AssetReaderEnum;
+use super::archive::ZipReader;
+use super::directory::DirectoryReader;
+use super::loader::LoadProgress;
+use super::packet::PacketReader;
+
+// Weak so the cache never keeps a pak buffer alive once every handle to it is gone
+enum CachedReader {
+    PacketReader(Weak<PacketReader<Cursor<Vec<u8>>>>),
+    ZipReader(Weak<ZipReader>),
+}
+
+impl CachedReader {
+    // Directories are reloaded on every open and never cached
+    fn new(reader: &AssetReaderEnum) -> Option<Self> {
+        match reader {
+            AssetReaderEnum::PacketReader(reader) => {
+                Some(CachedReader::PacketReader(Arc::downgrade(reader)))
+            }
+            AssetReaderEnum::ZipReader(reader) => {
+                Some(CachedReader::ZipReader(Arc::downgrade(reader)))
+            }
+            AssetReaderEnum::DirectoryReader(_) => None,
+        }
+    }
+
+    fn upgrade(&self) -> Option<AssetReaderEnum> {
+        match self {
+            CachedReader::PacketReader(reader) => {
+                reader.upgrade().map(AssetReaderEnum::PacketReader)
+            }
+            CachedReader::ZipReader(reader) => reader.upgrade().map(AssetReaderEnum::ZipReader),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            CachedReader::PacketReader(reader) => reader.strong_count() > 0,
+            CachedReader::ZipReader(reader) => reader.strong_count() > 0,
+        }
+    }
+
+    fn holds(&self, reader: &AssetReaderEnum) -> bool {
+        match (self, reader) {
+            (CachedReader::PacketReader(a), AssetReaderEnum::PacketReader(b)) => {
+                std::ptr::eq(a.as_ptr(), Arc::as_ptr(b))
+            }
+            (CachedReader::ZipReader(a), AssetReaderEnum::ZipReader(b)) => {
+                std::ptr::eq(a.as_ptr(), Arc::as_ptr(b))
+            }
+            _ => false,
+        }
+    }
+}
+
+struct CacheEntry {
+    modified: SystemTime,
+    length: u64,
+    reader: CachedReader,
+}
+
+// Opened pak and zip readers still in use somewhere, keyed by canonical path
+static READER_CACHE: LazyLock<Mutex<HashMap<PathBuf, CacheEntry>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn read_file(path: &str, progress: Option<&LoadProgress>) -> anyhow::Result<Vec<u8>> {
+    let Some(progress) = progress else {
+        return Ok(fs::read(path)?);
+    };
+
+    let mut file = fs::File::open(path)?;
+    let length = file.metadata()?.len();
+    progress.total_bytes.store(length, Ordering::Relaxed);
+
+    let mut bytes = Vec::with_capacity(length as usize);
+    let mut chunk = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..read]);
+        progress.add_bytes(read as u64);
+    }
+    Ok(bytes)
+}
+
+fn load(path: &str, progress: Option<&LoadProgress>) -> anyhow::Result<AssetReaderEnum> {
+    let reader = if path.ends_with(".pak") {
+        let cursor = Cursor::new(read_file(path, progress)?);
+        let packet_reader = PacketReader::new(cursor, progress)?;
+        AssetReaderEnum::PacketReader(Arc::new(packet_reader))
+    } else if path.ends_with(".zip") {
+        let zip_reader = ZipReader::new(read_file(path, progress)?)?;
+        AssetReaderEnum::ZipReader(Arc::new(zip_reader))
+    } else {
+        let directory_reader = DirectoryReader::new(path)?;
+        AssetReaderEnum::DirectoryReader(Arc::new(directory_reader))
+    };
+
+    if let Some(progress) = progress {
+        let entries = reader.as_reader().paths().len() as u64;
+        progress.total_entries.store(entries, Ordering::Relaxed);
+        progress.entries_indexed.store(entries, Ordering::Relaxed);
+    }
+    Ok(reader)
+}
+
+pub fn open(path: &str) -> anyhow::Result<AssetReaderEnum> {
+    open_with_progress(path, None)
+}
+
+pub fn open_with_progress(
+    path: &str,
+    progress: Option<&LoadProgress>,
+) -> anyhow::Result<AssetReaderEnum> {
+    let stat = fs::metadata(path)?;
+    // Directories are scanned on every open, their contents can change without the mtime
+    if stat.is_dir() {
+        return load(path, progress);
+    }
+
+    let canonical = fs::canonicalize(path)?;
+    let modified = stat.modified()?;
+    let length = stat.len();
+
+    {
+        let cache = READER_CACHE
+            .lock()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        if let Some(entry) = cache.get(&canonical)
+            && entry.modified == modified
+            && entry.length == length
+            && let Some(reader) = entry.reader.upgrade()
+        {
+            if let Some(progress) = progress {
+                progress.total_bytes.store(length, Ordering::Relaxed);
+                progress.bytes_read.store(length, Ordering::Relaxed);
+                let entries = reader.as_reader().paths().len() as u64;
+                progress.total_entries.store(entries, Ordering::Relaxed);
+                progress.entries_indexed.store(entries, Ordering::Relaxed);
+            }
+            return Ok(reader);
+        }
+    }
+
+    // Parse without holding the lock so other threads can still use the cache
+    let reader = load(path, progress)?;
+
+    let mut cache = READER_CACHE
+        .lock()
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    cache.retain(|_, entry| entry.reader.is_alive());
+    // Another thread may have loaded the same file meanwhile, everyone shares its reader
+    if let Some(entry) = cache.get(&canonical)
+        && entry.modified == modified
+        && entry.length == length
+        && let Some(existing) = entry.reader.upgrade()
+    {
+        return Ok(existing);
+    }
+    if let Some(cached) = CachedReader::new(&reader) {
+        cache.insert(
+            canonical,
+            CacheEntry {
+                modified,
+                length,
+                reader: cached,
+            },
+        );
+    }
+
+    Ok(reader)
+}
+
+// A handle is stale once the file it was read from has changed on disk
+pub fn is_stale(reader: &AssetReaderEnum) -> anyhow::Result<bool> {
+    if let AssetReaderEnum::DirectoryReader(_) = reader {
+        return Ok(false);
+    }
+
+    let mut cache = READER_CACHE
+        .lock()
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+    let Some((path, entry)) = cache.iter().find(|(_, entry)| entry.reader.holds(reader)) else {
+        // Replaced by a newer version of the same file
+        return Ok(true);
+    };
+    let stale = match fs::metadata(path) {
+        Ok(stat) => stat.modified()? != entry.modified || stat.len() != entry.length,
+        Err(_) => true,
+    };
+    // The next open reloads the file instead of handing out this reader
+    if stale {
+        let path = path.clone();
+        cache.remove(&path);
+    }
+    Ok(stale)
+}
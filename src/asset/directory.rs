@@ -25,7 +25,7 @@ impl DirectoryReader {
             if fs::exists(&meta_file)? {
                 directory_reader.metadata_file = Some(meta_name.to_string());
                 directory_reader.metadata =
-                    SBType::try_from(json::parse(&fs::read_to_string(&meta_file)?)?)?;
+                    super::jsonc::parse(&fs::read_to_string(&meta_file)?)?;
             }
         }
 
@@ -71,7 +71,7 @@ impl AssetReader for DirectoryReader {
         }
     }
 
-    fn file(&mut self, path: &str) -> anyhow::Result<super::file::AssetFile> {
+    fn file(&self, path: &str) -> anyhow::Result<super::file::AssetFile> {
         if !self.exist(path) {
             anyhow::bail!("File is not exist")
         }
@@ -102,4 +102,8 @@ impl AssetReader for DirectoryReader {
             }
         }
     }
+
+    fn metadata(&self) -> SBType {
+        self.metadata.clone()
+    }
 }
\ No newline at end of file
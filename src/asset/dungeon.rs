@@ -0,0 +1,596 @@
+use std::collections::HashMap;
+
+use indexmap::IndexMap;
+
+use super::AssetReader;
+use super::SBType;
+use super::path;
+use crate::utils::bitmap::Bitmap;
+
+const FLIP_FLAGS: u64 = 0xe000_0000;
+const LIQUID_COLOR: [u8; 4] = [64, 96, 224, 160];
+
+// Sizes come from the assets, so a product that doesn't fit is an error instead of a wrap
+fn scaled_canvas(
+    width: u32,
+    height: u32,
+    tile_width: u32,
+    tile_height: u32,
+) -> anyhow::Result<Bitmap> {
+    match (
+        width.checked_mul(tile_width),
+        height.checked_mul(tile_height),
+    ) {
+        (Some(width), Some(height)) => Ok(Bitmap::new(width, height)),
+        _ => anyhow::bail!(
+            "{}x{} tiles of {}x{} pixels is too large to render",
+            width,
+            height,
+            tile_width,
+            tile_height
+        ),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Brush {
+    pub kind: String,
+    pub value: Option<String>,
+}
+
+impl Brush {
+    // ["front", "dirt"], ["clear"], ["object", "door", {...}]
+    fn parse(value: &SBType) -> Option<Self> {
+        let array = value.as_array()?;
+        Some(Self {
+            kind: array.first()?.as_str()?.to_string(),
+            value: array.get(1).and_then(SBType::as_str).map(str::to_string),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DungeonPart {
+    pub name: String,
+    // "tmx" for Tiled maps, "image" for palette images
+    pub kind: String,
+    pub files: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dungeon {
+    pub path: String,
+    pub name: String,
+    pub anchors: Vec<String>,
+    pub parts: Vec<DungeonPart>,
+    // palette colour -> brushes for image parts
+    pub palette: Vec<([u8; 4], Vec<Brush>)>,
+}
+
+impl Dungeon {
+    pub(super) fn load(reader: &dyn AssetReader, dungeon_path: &str) -> anyhow::Result<Self> {
+        let value = reader.file(dungeon_path)?.as_json()?;
+        let metadata = value.get("metadata");
+
+        let strings = |value: Option<&SBType>| -> Vec<String> {
+            value
+                .and_then(SBType::as_array)
+                .map(|array| {
+                    array
+                        .iter()
+                        .filter_map(SBType::as_str)
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let mut parts = Vec::new();
+        for part in value
+            .get("parts")
+            .and_then(SBType::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let Some(name) = part.get("name").and_then(SBType::as_str) else {
+                continue;
+            };
+            let def = part.get("def").and_then(SBType::as_array);
+            let kind = def
+                .and_then(|def| def.first())
+                .and_then(SBType::as_str)
+                .unwrap_or_default();
+            // A part is either a single file or a stack of files drawn on top of each other
+            let files = match def.and_then(|def| def.get(1)) {
+                Some(SBType::String(file)) => vec![file.clone()],
+                files => strings(files),
+            };
+
+            parts.push(DungeonPart {
+                name: name.to_string(),
+                kind: kind.to_string(),
+                files: files
+                    .iter()
+                    .map(|file| path::resolve(dungeon_path, file))
+                    .collect(),
+            });
+        }
+
+        let mut palette = Vec::new();
+        for tile in value
+            .get("tiles")
+            .and_then(SBType::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let color = tile.get("value").and_then(SBType::as_array).map(|value| {
+                let channel = |i: usize| value.get(i).and_then(SBType::as_i64).unwrap_or(255) as u8;
+                [channel(0), channel(1), channel(2), channel(3)]
+            });
+            let brushes = tile
+                .get("brush")
+                .and_then(SBType::as_array)
+                .map(|brushes| brushes.iter().filter_map(Brush::parse).collect())
+                .unwrap_or_default();
+            if let Some(color) = color {
+                palette.push((color, brushes));
+            }
+        }
+
+        Ok(Self {
+            path: dungeon_path.to_string(),
+            name: metadata
+                .and_then(|metadata| metadata.get("name"))
+                .and_then(SBType::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            anchors: strings(metadata.and_then(|metadata| metadata.get("anchor"))),
+            parts,
+            palette,
+        })
+    }
+}
+
+struct Tileset {
+    path: String,
+    first_gid: u64,
+    properties: HashMap<u64, IndexMap<String, SBType>>,
+    images: HashMap<u64, String>,
+}
+
+impl Tileset {
+    fn load(tileset_path: &str, first_gid: u64, value: &SBType) -> Self {
+        let mut tileset = Self {
+            path: tileset_path.to_string(),
+            first_gid,
+            properties: HashMap::new(),
+            images: HashMap::new(),
+        };
+
+        // Older Tiled versions key tiles by id, newer ones store a list of tiles
+        if let Some(SBType::Object(properties)) = value.get("tileproperties") {
+            for (id, properties) in properties {
+                if let (Ok(id), SBType::Object(properties)) = (id.parse(), properties) {
+                    tileset.properties.insert(id, properties.clone());
+                }
+            }
+        }
+        match value.get("tiles") {
+            Some(SBType::Object(tiles)) => {
+                for (id, tile) in tiles {
+                    if let (Ok(id), Some(image)) =
+                        (id.parse(), tile.get("image").and_then(SBType::as_str))
+                    {
+                        tileset.images.insert(id, image.to_string());
+                    }
+                }
+            }
+            Some(SBType::Array(tiles)) => {
+                for tile in tiles {
+                    let Some(id) = tile.get("id").and_then(SBType::as_i64) else {
+                        continue;
+                    };
+                    let id = id as u64;
+                    if let Some(image) = tile.get("image").and_then(SBType::as_str) {
+                        tileset.images.insert(id, image.to_string());
+                    }
+                    let properties = tile.get("properties").and_then(SBType::as_array);
+                    for property in properties.into_iter().flatten() {
+                        if let (Some(name), Some(value)) = (
+                            property.get("name").and_then(SBType::as_str),
+                            property.get("value"),
+                        ) {
+                            tileset
+                                .properties
+                                .entry(id)
+                                .or_default()
+                                .insert(name.to_string(), value.clone());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        tileset
+    }
+}
+
+pub struct DungeonRenderer<'a> {
+    reader: &'a dyn AssetReader,
+    // material name -> texture path
+    materials: Option<HashMap<String, String>>,
+    colors: HashMap<String, Option<[u8; 4]>>,
+    images: HashMap<String, Option<Bitmap>>,
+    pub warnings: Vec<String>,
+}
+
+impl<'a> DungeonRenderer<'a> {
+    pub(super) fn new(reader: &'a dyn AssetReader) -> Self {
+        Self {
+            reader,
+            materials: None,
+            colors: HashMap::new(),
+            images: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn warn(&mut self, warning: String) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    fn image(&mut self, image_path: &str) -> Option<Bitmap> {
+        let image_path = path::image_file(image_path).to_string();
+        if let Some(image) = self.images.get(&image_path) {
+            return image.clone();
+        }
+
+        let image = match self
+            .reader
+            .file(&image_path)
+            .and_then(|file| Bitmap::from_png(&file.bytes))
+        {
+            Ok(image) => Some(image),
+            Err(e) => {
+                self.warn(format!("Failed to load image '{}': {}", image_path, e));
+                None
+            }
+        };
+        self.images.insert(image_path, image.clone());
+        image
+    }
+
+    fn material_color(&mut self, material: &str) -> Option<[u8; 4]> {
+        if let Some(color) = self.colors.get(material) {
+            return *color;
+        }
+
+        if self.materials.is_none() {
+            let mut materials = HashMap::new();
+            let paths: Vec<String> = self
+                .reader
+                .paths()
+                .into_iter()
+                .filter(|path| path.ends_with(".material"))
+                .cloned()
+                .collect();
+            for material_path in paths {
+                let Ok(value) = self.reader.file(&material_path).and_then(|f| f.as_json()) else {
+                    continue;
+                };
+                let name = value.get("materialName").and_then(SBType::as_str);
+                let texture = value
+                    .get("renderParameters")
+                    .and_then(|parameters| parameters.get("texture"))
+                    .and_then(SBType::as_str);
+                if let (Some(name), Some(texture)) = (name, texture) {
+                    materials.insert(name.to_string(), path::resolve(&material_path, texture));
+                }
+            }
+            self.materials = Some(materials);
+        }
+
+        let texture = self
+            .materials
+            .as_ref()
+            .and_then(|materials| materials.get(material))
+            .cloned();
+        let color = match texture {
+            Some(texture) => self.image(&texture).and_then(|image| image.average_color()),
+            None => {
+                self.warn(format!("Unknown material '{}'", material));
+                None
+            }
+        };
+        self.colors.insert(material.to_string(), color);
+        color
+    }
+
+    pub fn render_part(&mut self, dungeon: &Dungeon, part_name: &str) -> anyhow::Result<Bitmap> {
+        let part = match dungeon.parts.iter().find(|part| part.name == part_name) {
+            Some(part) => part,
+            None => anyhow::bail!("Dungeon has no part '{}'", part_name),
+        };
+
+        let mut layers = Vec::new();
+        for file in &part.files {
+            layers.push(match part.kind.as_str() {
+                "tmx" => self.render_map(file)?,
+                "image" => self.render_image(dungeon, file)?,
+                kind => anyhow::bail!("Unsupported part definition '{}'", kind),
+            });
+        }
+
+        let width = layers.iter().map(|layer| layer.width).max().unwrap_or(0);
+        let height = layers.iter().map(|layer| layer.height).max().unwrap_or(0);
+        let mut output = Bitmap::new(width, height);
+        // Stacked files share their bottom left corner
+        for layer in layers {
+            output.draw(&layer, 0, (height - layer.height) as i64);
+        }
+        Ok(output)
+    }
+
+    fn render_image(&mut self, dungeon: &Dungeon, image_path: &str) -> anyhow::Result<Bitmap> {
+        let source = match self.image(image_path) {
+            Some(source) => source,
+            None => anyhow::bail!("Failed to load part image '{}'", image_path),
+        };
+
+        let mut output = scaled_canvas(source.width, source.height, 8, 8)?;
+        for y in 0..source.height {
+            for x in 0..source.width {
+                let pixel = source.pixel(x, y);
+                let brushes = match dungeon.palette.iter().find(|(color, _)| *color == pixel) {
+                    Some((_, brushes)) => brushes,
+                    None => {
+                        self.warn(format!("Unknown palette colour {:?}", pixel));
+                        continue;
+                    }
+                };
+                for brush in brushes {
+                    let color = match (brush.kind.as_str(), &brush.value) {
+                        ("front", Some(material)) => self.material_color(material),
+                        ("back", Some(material)) => self.material_color(material).map(darken),
+                        ("liquid", _) => Some(LIQUID_COLOR),
+                        _ => None,
+                    };
+                    if let Some(color) = color {
+                        output.fill_rect(x as i64 * 8, y as i64 * 8, 8, 8, color);
+                    }
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    fn render_map(&mut self, map_path: &str) -> anyhow::Result<Bitmap> {
+        let map = self.reader.file(map_path)?.as_json()?;
+        let dimension = |key: &str, default: i64| {
+            map.get(key)
+                .and_then(SBType::as_i64)
+                .unwrap_or(default)
+                .clamp(0, u32::MAX as i64) as u32
+        };
+        let (tile_width, tile_height) = (dimension("tilewidth", 8), dimension("tileheight", 8));
+        let (width, height) = (dimension("width", 0), dimension("height", 0));
+
+        let mut tilesets = Vec::new();
+        for tileset in map
+            .get("tilesets")
+            .and_then(SBType::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let first_gid = tileset
+                .get("firstgid")
+                .and_then(SBType::as_i64)
+                .unwrap_or(1) as u64;
+            match tileset.get("source").and_then(SBType::as_str) {
+                Some(source) => {
+                    let source = path::resolve(map_path, source);
+                    match self.reader.file(&source).and_then(|file| file.as_json()) {
+                        Ok(value) => tilesets.push(Tileset::load(&source, first_gid, &value)),
+                        Err(e) => self.warn(format!("Failed to load tileset '{}': {}", source, e)),
+                    }
+                }
+                None => tilesets.push(Tileset::load(map_path, first_gid, tileset)),
+            }
+        }
+        tilesets.sort_by_key(|tileset| tileset.first_gid);
+
+        let mut output = scaled_canvas(width, height, tile_width, tile_height)?;
+        for layer in map
+            .get("layers")
+            .and_then(SBType::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let name = layer
+                .get("name")
+                .and_then(SBType::as_str)
+                .unwrap_or_default();
+            let background = name == "back";
+
+            match layer.get("type").and_then(SBType::as_str) {
+                Some("tilelayer") => {
+                    let Some(data) = layer.get("data").and_then(SBType::as_array) else {
+                        self.warn(format!("Layer '{}' has no plain tile data", name));
+                        continue;
+                    };
+                    let layer_width = layer
+                        .get("width")
+                        .and_then(SBType::as_i64)
+                        .unwrap_or(width as i64);
+                    for (i, gid) in data.iter().enumerate() {
+                        let gid = gid.as_i64().unwrap_or(0) as u64 & !FLIP_FLAGS;
+                        if gid == 0 || layer_width <= 0 {
+                            continue;
+                        }
+                        let x = (i as i64 % layer_width) * tile_width as i64;
+                        let bottom = (i as i64 / layer_width + 1) * tile_height as i64;
+                        self.draw_tile(
+                            &mut output,
+                            &tilesets,
+                            gid,
+                            x,
+                            bottom,
+                            background,
+                            (tile_width, tile_height),
+                        );
+                    }
+                }
+                Some("objectgroup") => {
+                    for object in layer
+                        .get("objects")
+                        .and_then(SBType::as_array)
+                        .into_iter()
+                        .flatten()
+                    {
+                        let gid = object.get("gid").and_then(SBType::as_i64).unwrap_or(0) as u64
+                            & !FLIP_FLAGS;
+                        if gid == 0 {
+                            continue;
+                        }
+                        let coordinate = |key: &str| {
+                            object.get(key).and_then(SBType::as_f64).unwrap_or(0.0) as i64
+                        };
+                        // Tile objects are anchored at their bottom left corner
+                        self.draw_tile(
+                            &mut output,
+                            &tilesets,
+                            gid,
+                            coordinate("x"),
+                            coordinate("y"),
+                            background,
+                            (tile_width, tile_height),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(output)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_tile(
+        &mut self,
+        output: &mut Bitmap,
+        tilesets: &[Tileset],
+        gid: u64,
+        x: i64,
+        bottom: i64,
+        background: bool,
+        (tile_width, tile_height): (u32, u32),
+    ) {
+        let Some(tileset) = tilesets
+            .iter()
+            .rev()
+            .find(|tileset| tileset.first_gid <= gid)
+        else {
+            self.warn(format!("Tile {} has no tileset", gid));
+            return;
+        };
+        let id = gid - tileset.first_gid;
+        let properties = tileset.properties.get(&id);
+        let property = |key: &str| {
+            properties
+                .and_then(|properties| properties.get(key))
+                .and_then(SBType::as_str)
+                .map(str::to_string)
+        };
+
+        // Invisible helper tiles such as anchors and "empty" markers
+        if property("invalid").is_some() || property("clear").is_some() {
+            return;
+        }
+
+        let image = tileset
+            .images
+            .get(&id)
+            .map(|image| path::resolve(&tileset.path, image))
+            .and_then(|image| self.image(&image));
+
+        if let Some(mut image) = image {
+            if background {
+                image.data.chunks_exact_mut(4).for_each(|pixel| {
+                    let darker = darken([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                    pixel.copy_from_slice(&darker);
+                });
+            }
+            output.draw(&image, x, bottom - image.height as i64);
+            return;
+        }
+
+        let color = if let Some(material) = property("material") {
+            self.material_color(&material)
+        } else if property("liquid").is_some() {
+            Some(LIQUID_COLOR)
+        } else {
+            None
+        };
+        if let Some(color) = color {
+            let color = if background { darken(color) } else { color };
+            output.fill_rect(
+                x,
+                bottom - tile_height as i64,
+                tile_width,
+                tile_height,
+                color,
+            );
+        }
+    }
+}
+
+fn darken(pixel: [u8; 4]) -> [u8; 4] {
+    [pixel[0] / 2, pixel[1] / 2, pixel[2] / 2, pixel[3]]
+}
+
+impl From<&Dungeon> for SBType {
+    fn from(value: &Dungeon) -> Self {
+        let strings = |strings: &Vec<String>| {
+            SBType::Array(strings.iter().cloned().map(SBType::String).collect())
+        };
+        let parts = value
+            .parts
+            .iter()
+            .map(|part| {
+                SBType::Object(
+                    [
+                        ("name".to_string(), SBType::String(part.name.clone())),
+                        ("kind".to_string(), SBType::String(part.kind.clone())),
+                        ("files".to_string(), strings(&part.files)),
+                    ]
+                    .into_iter()
+                    .collect(),
+                )
+            })
+            .collect();
+
+        SBType::Object(
+            [
+                ("path".to_string(), SBType::String(value.path.clone())),
+                ("name".to_string(), SBType::String(value.name.clone())),
+                ("anchors".to_string(), strings(&value.anchors)),
+                ("parts".to_string(), SBType::Array(parts)),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn oversized_canvases_are_errors() {
+        let canvas = super::scaled_canvas(3, 2, 8, 16).unwrap();
+        assert_eq!((canvas.width, canvas.height), (24, 32));
+        assert!(super::scaled_canvas(u32::MAX / 4, 1, 8, 8).is_err());
+        assert!(super::scaled_canvas(1, 1 << 20, 8, 1 << 12).is_err());
+    }
+}
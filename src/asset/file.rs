@@ -1,5 +1,7 @@
 use mlua::UserData;
 
+use super::SBType;
+
 #[derive(Debug, Clone)]
 pub struct AssetFile {
     pub path: String,
@@ -10,6 +12,10 @@ impl AssetFile {
     pub fn as_string(&self) -> anyhow::Result<String> {
         Ok(self.bytes.iter().map(|&b| b as char).collect::<String>())
     }
+
+    pub fn as_json(&self) -> anyhow::Result<SBType> {
+        super::jsonc::parse(&String::from_utf8_lossy(&self.bytes))
+    }
 }
 
 impl UserData for AssetFile {
@@ -20,6 +26,18 @@ impl UserData for AssetFile {
             this.as_string().map_err(|e| mlua::Error::external(e))
         });
 
+        methods.add_method("as_json", |_, this, _: ()| {
+            this.as_json().map_err(mlua::Error::external)
+        });
+
+        // `music` defaults to whether the file lives under /music/
+        methods.add_method("audio_info", |_, this, music: Option<bool>| {
+            let music = music.unwrap_or_else(|| super::audio::is_music(&this.path));
+            super::audio::inspect(&this.bytes, music)
+                .map(|info| SBType::from(&info))
+                .map_err(mlua::Error::external)
+        });
+
         methods.add_method("path", |_, this, _: ()| {
             Ok(this.path.clone())
         });
@@ -0,0 +1,125 @@
+use indexmap::IndexMap;
+
+use super::AssetReader;
+use super::SBType;
+use super::path;
+
+// Parsed `.frames` file, frame rectangles are [left, top, right, bottom] in pixels
+#[derive(Debug, Clone, Default)]
+pub struct Frames {
+    pub frames: IndexMap<String, [u32; 4]>,
+    pub aliases: IndexMap<String, String>,
+}
+
+impl Frames {
+    pub fn parse(value: &SBType) -> anyhow::Result<Self> {
+        let mut frames = Self::default();
+        let pair = |value: Option<&SBType>| -> Option<[u32; 2]> {
+            let array = value?.as_array()?;
+            Some([
+                array.first()?.as_i64()?.max(0) as u32,
+                array.get(1)?.as_i64()?.max(0) as u32,
+            ])
+        };
+
+        if let Some(grid) = value.get("frameGrid") {
+            let Some([width, height]) = pair(grid.get("size")) else {
+                anyhow::bail!("frameGrid is missing size");
+            };
+            let Some([columns, rows]) = pair(grid.get("dimensions")) else {
+                anyhow::bail!("frameGrid is missing dimensions");
+            };
+            let [left, top] = pair(grid.get("begin")).unwrap_or([0, 0]);
+            let names = grid.get("names").and_then(SBType::as_array);
+
+            for row in 0..rows {
+                for column in 0..columns {
+                    // Without names the frames are numbered left to right, top to bottom
+                    let name = match names {
+                        Some(names) => names
+                            .get(row as usize)
+                            .and_then(SBType::as_array)
+                            .and_then(|names| names.get(column as usize))
+                            .and_then(SBType::as_str)
+                            .map(str::to_string),
+                        None => Some((row * columns + column).to_string()),
+                    };
+                    if let Some(name) = name {
+                        let x = left + column * width;
+                        let y = top + row * height;
+                        frames.frames.insert(name, [x, y, x + width, y + height]);
+                    }
+                }
+            }
+        }
+
+        if let Some(SBType::Object(list)) = value.get("frameList") {
+            for (name, rect) in list {
+                let rect = rect.as_array().and_then(|rect| {
+                    let values: Vec<u32> = rect
+                        .iter()
+                        .filter_map(SBType::as_i64)
+                        .map(|v| v.max(0) as u32)
+                        .collect();
+                    values.try_into().ok()
+                });
+                match rect {
+                    Some(rect) => {
+                        frames.frames.insert(name.clone(), rect);
+                    }
+                    None => anyhow::bail!("Frame '{}' has an invalid rectangle", name),
+                }
+            }
+        }
+
+        if let Some(SBType::Object(aliases)) = value.get("aliases") {
+            for (alias, target) in aliases {
+                if let Some(target) = target.as_str() {
+                    frames.aliases.insert(alias.clone(), target.to_string());
+                }
+            }
+        }
+
+        Ok(frames)
+    }
+
+    // Resolves aliases, returning the rectangle of the frame
+    pub fn frame(&self, name: &str) -> Option<[u32; 4]> {
+        let name = self.aliases.get(name).map(String::as_str).unwrap_or(name);
+        self.frames.get(name).copied()
+    }
+}
+
+// The game looks for `<image>.frames` next to the image, then `default.frames` in the
+// image's directory and every parent directory
+pub(super) fn frames_path(reader: &dyn AssetReader, image: &str) -> Option<String> {
+    let image = path::image_file(image);
+    let specific = match image.rfind('.') {
+        Some(index) if index > image.rfind('/').unwrap_or(0) => {
+            format!("{}.frames", &image[..index])
+        }
+        _ => format!("{}.frames", image),
+    };
+    if reader.exist(&specific) {
+        return Some(specific);
+    }
+
+    let mut directory = path::directory(image);
+    loop {
+        let default = format!("{}default.frames", directory);
+        if reader.exist(&default) {
+            return Some(default);
+        }
+        if directory == "/" {
+            return None;
+        }
+        directory = path::directory(&directory[..directory.len() - 1]);
+    }
+}
+
+pub(super) fn load(reader: &dyn AssetReader, image: &str) -> anyhow::Result<Option<Frames>> {
+    match frames_path(reader, image) {
+        Some(frames_path) => Ok(Some(Frames::parse(&reader.file(&frames_path)?.as_json()?)?)),
+        None => Ok(None),
+    }
+}
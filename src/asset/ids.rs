@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use indexmap::IndexMap;
+
+use super::AssetReader;
+use super::SBType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdKind {
+    Material,
+    Mod,
+    Liquid,
+}
+
+impl IdKind {
+    fn from_path(path: &str) -> Option<Self> {
+        if path.ends_with(".material") {
+            Some(IdKind::Material)
+        } else if path.ends_with(".matmod") {
+            Some(IdKind::Mod)
+        } else if path.ends_with(".liquid") {
+            Some(IdKind::Liquid)
+        } else {
+            None
+        }
+    }
+
+    fn keys(&self) -> (&'static str, &'static str) {
+        match self {
+            IdKind::Material => ("materialId", "materialName"),
+            IdKind::Mod => ("modId", "modName"),
+            IdKind::Liquid => ("liquidId", "name"),
+        }
+    }
+
+    // Ids content may use, the rest belong to the engine's empty and meta entries
+    fn valid(&self) -> RangeInclusive<i64> {
+        match self {
+            IdKind::Material => 1..=65499,
+            IdKind::Mod => 1..=65519,
+            IdKind::Liquid => 1..=255,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            IdKind::Material => "materials",
+            IdKind::Mod => "mods",
+            IdKind::Liquid => "liquids",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IdEntry {
+    // 1-based position of the reader in the layer list
+    pub source: usize,
+    pub source_name: Option<String>,
+    pub path: String,
+    pub name: String,
+    pub id: Option<i64>,
+}
+
+fn entry_to_sbtype(entry: &IdEntry) -> SBType {
+    let mut map = IndexMap::new();
+    map.insert("source".to_string(), SBType::Int(entry.source as i64));
+    if let Some(source_name) = &entry.source_name {
+        map.insert("mod".to_string(), SBType::String(source_name.clone()));
+    }
+    map.insert("path".to_string(), SBType::String(entry.path.clone()));
+    map.insert("name".to_string(), SBType::String(entry.name.clone()));
+    if let Some(id) = entry.id {
+        map.insert("id".to_string(), SBType::Int(id));
+    }
+    SBType::Object(map)
+}
+
+#[derive(Debug, Default)]
+pub struct IdReport {
+    pub entries: Vec<(IdKind, IdEntry)>,
+    pub errors: Vec<(String, String)>,
+}
+
+impl IdReport {
+    // Later readers override assets at the same path, like the game's asset layering
+    pub(super) fn build(readers: &[&dyn AssetReader]) -> Self {
+        let mut report = Self::default();
+        let mut assets: BTreeMap<String, usize> = BTreeMap::new();
+        for (index, reader) in readers.iter().enumerate() {
+            for path in reader.paths() {
+                if IdKind::from_path(path).is_some() {
+                    assets.insert(path.clone(), index);
+                }
+            }
+        }
+
+        for (path, index) in assets {
+            let Some(kind) = IdKind::from_path(&path) else {
+                continue;
+            };
+            let reader = readers[index];
+            let value = match reader.file(&path).and_then(|file| file.as_json()) {
+                Ok(value) => value,
+                Err(e) => {
+                    report.errors.push((path, e.to_string()));
+                    continue;
+                }
+            };
+
+            let (id_key, name_key) = kind.keys();
+            report.entries.push((
+                kind,
+                IdEntry {
+                    source: index + 1,
+                    source_name: reader
+                        .meta("name".to_string())
+                        .ok()
+                        .and_then(|name| name.as_str().map(str::to_string)),
+                    name: value
+                        .get(name_key)
+                        .and_then(SBType::as_str)
+                        .unwrap_or_default()
+                        .to_string(),
+                    id: value.get(id_key).and_then(SBType::as_i64),
+                    path,
+                },
+            ));
+        }
+
+        report
+    }
+
+    fn of_kind(&self, kind: IdKind) -> impl Iterator<Item = &IdEntry> {
+        self.entries
+            .iter()
+            .filter(move |(entry_kind, _)| *entry_kind == kind)
+            .map(|(_, entry)| entry)
+    }
+
+    pub fn collisions(&self, kind: IdKind) -> Vec<(i64, Vec<&IdEntry>)> {
+        let mut by_id: BTreeMap<i64, Vec<&IdEntry>> = BTreeMap::new();
+        for entry in self.of_kind(kind) {
+            if let Some(id) = entry.id {
+                by_id.entry(id).or_default().push(entry);
+            }
+        }
+        by_id
+            .into_iter()
+            .filter(|(_, entries)| entries.len() > 1)
+            .collect()
+    }
+
+    // Entries without an id or with one outside the range content may use
+    pub fn reserved(&self, kind: IdKind) -> Vec<&IdEntry> {
+        let valid = kind.valid();
+        self.of_kind(kind)
+            .filter(|entry| !entry.id.is_some_and(|id| valid.contains(&id)))
+            .collect()
+    }
+
+    // Gaps in the valid range at least `size` ids long, lowest first
+    pub fn free_ranges(&self, kind: IdKind, size: i64, limit: usize) -> Vec<(i64, i64)> {
+        let valid = kind.valid();
+        let mut used: Vec<i64> = self
+            .of_kind(kind)
+            .filter_map(|entry| entry.id)
+            .filter(|id| valid.contains(id))
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+
+        let mut ranges = Vec::new();
+        let mut next = *valid.start();
+        for id in used.into_iter().chain(std::iter::once(valid.end() + 1)) {
+            if id - next >= size.max(1) {
+                ranges.push((next, id - 1));
+                if ranges.len() >= limit {
+                    break;
+                }
+            }
+            next = id + 1;
+        }
+        ranges
+    }
+
+    pub fn to_sbtype(&self, free_size: i64, free_limit: usize) -> SBType {
+        let mut report = IndexMap::new();
+        for kind in [IdKind::Material, IdKind::Mod, IdKind::Liquid] {
+            let collisions = self
+                .collisions(kind)
+                .into_iter()
+                .map(|(id, entries)| {
+                    let mut map = IndexMap::new();
+                    map.insert("id".to_string(), SBType::Int(id));
+                    map.insert(
+                        "assets".to_string(),
+                        SBType::Array(entries.into_iter().map(entry_to_sbtype).collect()),
+                    );
+                    SBType::Object(map)
+                })
+                .collect();
+            let reserved = self
+                .reserved(kind)
+                .into_iter()
+                .map(entry_to_sbtype)
+                .collect();
+            let free = self
+                .free_ranges(kind, free_size, free_limit)
+                .into_iter()
+                .map(|(first, last)| SBType::Array(vec![SBType::Int(first), SBType::Int(last)]))
+                .collect();
+
+            let mut map = IndexMap::new();
+            map.insert(
+                "count".to_string(),
+                SBType::Int(self.of_kind(kind).count() as i64),
+            );
+            map.insert("collisions".to_string(), SBType::Array(collisions));
+            map.insert("reserved".to_string(), SBType::Array(reserved));
+            map.insert("free".to_string(), SBType::Array(free));
+            report.insert(kind.name().to_string(), SBType::Object(map));
+        }
+
+        let errors = self
+            .errors
+            .iter()
+            .map(|(path, error)| (path.clone(), SBType::String(error.clone())))
+            .collect();
+        report.insert("errors".to_string(), SBType::Object(errors));
+
+        SBType::Object(report)
+    }
+}
@@ -0,0 +1,414 @@
+use indexmap::IndexMap;
+
+use super::SBType;
+
+// JSON as Starbound reads it: `//` and `/* */` comments are allowed, integer literals stay
+// integers with full i64 precision and anything with a fraction or exponent stays a float.
+struct Parser<'a> {
+    source: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> anyhow::Result<T> {
+        anyhow::bail!("{} at byte {}", message, self.position)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) -> anyhow::Result<()> {
+        loop {
+            match (self.peek(), self.source.get(self.position + 1)) {
+                (Some(b' ' | b'\t' | b'\n' | b'\r'), _) => self.position += 1,
+                (Some(b'/'), Some(b'/')) => {
+                    while !matches!(self.peek(), None | Some(b'\n')) {
+                        self.position += 1;
+                    }
+                }
+                (Some(b'/'), Some(b'*')) => {
+                    let start = self.position;
+                    self.position += 2;
+                    loop {
+                        match self.peek() {
+                            None => {
+                                self.position = start;
+                                return self.error("Unterminated comment");
+                            }
+                            Some(b'*') if self.source.get(self.position + 1) == Some(&b'/') => {
+                                self.position += 2;
+                                break;
+                            }
+                            _ => self.position += 1,
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> anyhow::Result<()> {
+        if self.source[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(())
+        } else {
+            self.error(&format!("Expected '{}'", literal))
+        }
+    }
+
+    fn parse_value(&mut self) -> anyhow::Result<SBType> {
+        self.skip_whitespace()?;
+        match self.peek() {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => Ok(SBType::String(self.parse_string()?)),
+            Some(b't') => self.expect("true").map(|_| SBType::Boolean(true)),
+            Some(b'f') => self.expect("false").map(|_| SBType::Boolean(false)),
+            Some(b'n') => self.expect("null").map(|_| SBType::Nil),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => self.error("Unexpected character"),
+            None => self.error("Unexpected end of input"),
+        }
+    }
+
+    fn parse_object(&mut self) -> anyhow::Result<SBType> {
+        self.position += 1;
+        let mut map = IndexMap::new();
+
+        self.skip_whitespace()?;
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(SBType::Object(map));
+        }
+
+        loop {
+            self.skip_whitespace()?;
+            if self.peek() != Some(b'"') {
+                return self.error("Expected object key");
+            }
+            let key = self.parse_string()?;
+
+            self.skip_whitespace()?;
+            self.expect(":")?;
+            let value = self.parse_value()?;
+            map.insert(key, value);
+
+            self.skip_whitespace()?;
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(SBType::Object(map));
+                }
+                _ => return self.error("Expected ',' or '}'"),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> anyhow::Result<SBType> {
+        self.position += 1;
+        let mut array = Vec::new();
+
+        self.skip_whitespace()?;
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(SBType::Array(array));
+        }
+
+        loop {
+            array.push(self.parse_value()?);
+
+            self.skip_whitespace()?;
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(SBType::Array(array));
+                }
+                _ => return self.error("Expected ',' or ']'"),
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> anyhow::Result<u32> {
+        let digits = self
+            .source
+            .get(self.position..self.position + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        match digits {
+            Some(value) => {
+                self.position += 4;
+                Ok(value)
+            }
+            None => self.error("Invalid unicode escape"),
+        }
+    }
+
+    // Called after `\u`, joins surrogate pairs into a single character
+    fn parse_unicode_escape(&mut self) -> anyhow::Result<char> {
+        let high = self.parse_hex4()?;
+        if !(0xd800..0xdc00).contains(&high) || !self.source[self.position..].starts_with(b"\\u") {
+            return Ok(char::from_u32(high).unwrap_or(char::REPLACEMENT_CHARACTER));
+        }
+
+        self.position += 2;
+        let low = self.parse_hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Ok(char::REPLACEMENT_CHARACTER);
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn parse_string(&mut self) -> anyhow::Result<String> {
+        let start = self.position;
+        self.position += 1;
+        let mut bytes = Vec::new();
+
+        loop {
+            match self.peek() {
+                None => {
+                    self.position = start;
+                    return self.error("Unterminated string");
+                }
+                Some(b'"') => {
+                    self.position += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.position += 1;
+                            let mut buffer = [0u8; 4];
+                            let c = self.parse_unicode_escape()?;
+                            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                            continue;
+                        }
+                        _ => return self.error("Invalid escape"),
+                    };
+                    self.position += 1;
+                    let mut buffer = [0u8; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(byte) => {
+                    bytes.push(byte);
+                    self.position += 1;
+                }
+            }
+        }
+
+        match String::from_utf8(bytes) {
+            Ok(string) => Ok(string),
+            Err(_) => {
+                self.position = start;
+                self.error("Invalid UTF-8 in string")
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> anyhow::Result<SBType> {
+        let start = self.position;
+        let mut is_float = false;
+
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'0'..=b'9' => {}
+                b'.' | b'e' | b'E' => is_float = true,
+                b'+' | b'-' if is_float => {}
+                _ => break,
+            }
+            self.position += 1;
+        }
+
+        let literal = std::str::from_utf8(&self.source[start..self.position])?;
+        // Ints are stored as i64, larger ones fall back to a float like the game does
+        if !is_float && let Ok(int) = literal.parse::<i64>() {
+            return Ok(SBType::Int(int));
+        }
+        match literal.parse::<f64>() {
+            Ok(float) => Ok(SBType::Float(float)),
+            Err(_) => {
+                self.position = start;
+                self.error("Invalid number")
+            }
+        }
+    }
+}
+
+pub fn parse(source: &str) -> anyhow::Result<SBType> {
+    let mut parser = Parser {
+        source: source.as_bytes(),
+        position: 0,
+    };
+    // Skip a UTF-8 byte order mark
+    if source.starts_with('\u{feff}') {
+        parser.position = 3;
+    }
+
+    let value = parser.parse_value()?;
+    parser.skip_whitespace()?;
+    if parser.position != parser.source.len() {
+        return parser.error("Unexpected trailing characters");
+    }
+    Ok(value)
+}
+
+fn write_string(output: &mut String, string: &str) {
+    output.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            '\u{8}' => output.push_str("\\b"),
+            '\u{c}' => output.push_str("\\f"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
+
+fn write_value(output: &mut String, value: &SBType, indent: Option<usize>, depth: usize) {
+    let newline = |output: &mut String, depth: usize| {
+        if let Some(indent) = indent {
+            output.push('\n');
+            output.push_str(&" ".repeat(indent * depth));
+        }
+    };
+
+    match value {
+        SBType::Nil => output.push_str("null"),
+        SBType::Boolean(v) => output.push_str(if *v { "true" } else { "false" }),
+        SBType::Int(v) => output.push_str(&v.to_string()),
+        // `{:?}` always keeps a fraction or exponent, so floats read back as floats
+        SBType::Float(v) if v.is_finite() => output.push_str(&format!("{:?}", v)),
+        SBType::Float(_) => output.push_str("null"),
+        SBType::String(v) => write_string(output, v),
+        SBType::Array(array) => {
+            if array.is_empty() {
+                output.push_str("[]");
+                return;
+            }
+            output.push('[');
+            for (i, item) in array.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                newline(output, depth + 1);
+                write_value(output, item, indent, depth + 1);
+            }
+            newline(output, depth);
+            output.push(']');
+        }
+        SBType::Object(map) => {
+            if map.is_empty() {
+                output.push_str("{}");
+                return;
+            }
+            output.push('{');
+            for (i, (key, item)) in map.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                newline(output, depth + 1);
+                write_string(output, key);
+                output.push_str(if indent.is_some() { ": " } else { ":" });
+                write_value(output, item, indent, depth + 1);
+            }
+            newline(output, depth);
+            output.push('}');
+        }
+    }
+}
+
+pub fn stringify(value: &SBType, indent: Option<usize>) -> String {
+    let mut output = String::new();
+    write_value(&mut output, value, indent, 0);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::super::SBType;
+    use super::super::reader::SBReader;
+    use super::super::writer::SBWriter;
+
+    // Same kind and the same bits, so -0.0 and 0.0 differ
+    fn same_number(a: &SBType, b: &SBType) -> bool {
+        match (a, b) {
+            (SBType::Int(a), SBType::Int(b)) => a == b,
+            (SBType::Float(a), SBType::Float(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+
+    fn round_trip(value: &SBType) -> SBType {
+        let mut bytes = Vec::new();
+        bytes.write_object(value).unwrap();
+        Cursor::new(bytes).read_object().unwrap()
+    }
+
+    fn parse_both(literal: &str) -> [anyhow::Result<SBType>; 2] {
+        [
+            super::parse(literal),
+            json::parse(literal)
+                .map_err(anyhow::Error::from)
+                .and_then(SBType::try_from),
+        ]
+    }
+
+    #[test]
+    fn numbers_keep_their_kind() {
+        let cases = [
+            ("1", SBType::Int(1)),
+            ("1.0", SBType::Float(1.0)),
+            ("-0.0", SBType::Float(-0.0)),
+            ("1e2", SBType::Float(100.0)),
+            ("-9223372036854775808", SBType::Int(i64::MIN)),
+            ("9223372036854775807", SBType::Int(i64::MAX)),
+            ("9007199254740993", SBType::Int((1 << 53) + 1)),
+            ("9223372036854775808", SBType::Float(i64::MAX as f64)),
+            ("-9223372036854775809", SBType::Float(i64::MIN as f64)),
+            ("18446744073709551615", SBType::Float(u64::MAX as f64)),
+            ("100000000000000000000", SBType::Float(1e20)),
+        ];
+        for (literal, expected) in cases {
+            for parsed in parse_both(literal) {
+                let parsed = parsed.unwrap();
+                assert!(
+                    same_number(&parsed, &expected),
+                    "{} parsed as {:?}",
+                    literal,
+                    parsed
+                );
+                let written = round_trip(&parsed);
+                assert!(
+                    same_number(&written, &expected),
+                    "{} round-tripped as {:?}",
+                    literal,
+                    written
+                );
+            }
+        }
+    }
+}
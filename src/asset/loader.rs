@@ -0,0 +1,124 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use super::AssetReaderEnum;
+
+#[derive(Debug, Default)]
+pub struct LoadProgress {
+    pub bytes_read: AtomicU64,
+    pub total_bytes: AtomicU64,
+    pub entries_indexed: AtomicU64,
+    pub total_entries: AtomicU64,
+    pub done: AtomicBool,
+}
+
+impl LoadProgress {
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_entry(&self) {
+        self.entries_indexed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+enum LoadState {
+    Running(JoinHandle<anyhow::Result<AssetReaderEnum>>),
+    Finished(AssetReaderEnum),
+    Failed(String),
+}
+
+pub struct PendingReader {
+    progress: Arc<LoadProgress>,
+    state: Mutex<Option<LoadState>>,
+}
+
+impl PendingReader {
+    pub fn spawn(path: String) -> Self {
+        let progress = Arc::new(LoadProgress::default());
+        let worker_progress = progress.clone();
+
+        let handle = thread::spawn(move || {
+            let result = super::cache::open_with_progress(&path, Some(&worker_progress));
+            worker_progress.done.store(true, Ordering::Release);
+            result
+        });
+
+        Self {
+            progress,
+            state: Mutex::new(Some(LoadState::Running(handle))),
+        }
+    }
+
+    // Returns the reader once loading has finished, without blocking unless `wait` is set
+    pub fn result(&self, wait: bool) -> anyhow::Result<Option<AssetReaderEnum>> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+        // `done` is set right before the worker returns, so joining then only waits briefly
+        if let Some(LoadState::Running(handle)) = state.as_ref()
+            && !wait
+            && !handle.is_finished()
+            && !self.progress.done.load(Ordering::Acquire)
+        {
+            return Ok(None);
+        }
+
+        let next = match state.take() {
+            Some(LoadState::Running(handle)) => match handle.join() {
+                Ok(Ok(reader)) => LoadState::Finished(reader),
+                Ok(Err(e)) => LoadState::Failed(e.to_string()),
+                Err(_) => LoadState::Failed("Loader thread panicked".to_string()),
+            },
+            Some(next) => next,
+            None => anyhow::bail!("Loader state is missing"),
+        };
+
+        let result = match &next {
+            LoadState::Finished(reader) => Ok(Some(reader.clone())),
+            LoadState::Failed(e) => Err(anyhow::anyhow!(e.clone())),
+            LoadState::Running(_) => Ok(None),
+        };
+        *state = Some(next);
+        result
+    }
+}
+
+impl mlua::UserData for PendingReader {
+    fn add_fields<F: mlua::UserDataFields<Self>>(_: &mut F) {}
+
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("progress", |lua, this, _: ()| {
+            let progress = &this.progress;
+            let table = lua.create_table()?;
+            table.set("bytes_read", progress.bytes_read.load(Ordering::Relaxed))?;
+            table.set("total_bytes", progress.total_bytes.load(Ordering::Relaxed))?;
+            table.set(
+                "entries_indexed",
+                progress.entries_indexed.load(Ordering::Relaxed),
+            )?;
+            table.set(
+                "total_entries",
+                progress.total_entries.load(Ordering::Relaxed),
+            )?;
+            table.set("done", progress.done.load(Ordering::Acquire))?;
+            Ok(table)
+        });
+
+        methods.add_method("done", |_, this, _: ()| {
+            Ok(this.progress.done.load(Ordering::Acquire))
+        });
+
+        // nil while the pak is still loading, raises the load error if it failed
+        methods.add_method("reader", |_, this, _: ()| {
+            this.result(false).map_err(|e| e.into())
+        });
+
+        methods.add_method("wait", |_, this, _: ()| {
+            this.result(true).map_err(|e| e.into())
+        });
+    }
+}
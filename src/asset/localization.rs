@@ -0,0 +1,344 @@
+use std::fs;
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use super::AssetReader;
+use super::SBType;
+use super::jsonc;
+use super::path;
+
+// Keys holding user-facing text, by asset extension
+fn text_keys(extension: &str) -> &'static [&'static str] {
+    match extension {
+        "codex" => &["title", "shortdescription", "description", "contentPages"],
+        "questtemplate" => &[
+            "title",
+            "text",
+            "completionText",
+            "failureText",
+            "shortdescription",
+            "description",
+        ],
+        "radiomessages" => &["text"],
+        _ => &["shortdescription", "description"],
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: String,
+    // JSON pointer (RFC 6901) to the string inside the asset
+    pub pointer: String,
+    pub source: String,
+    pub translation: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatalogFormat {
+    Json,
+    Po,
+}
+
+impl CatalogFormat {
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".po") || path.ends_with(".pot") {
+            CatalogFormat::Po
+        } else {
+            CatalogFormat::Json
+        }
+    }
+}
+
+fn escape_pointer(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn collect(value: &SBType, pointer: &str, keys: &[&str], path: &str, entries: &mut Vec<Entry>) {
+    let push = |entries: &mut Vec<Entry>, pointer: String, text: &str| {
+        if !text.trim().is_empty() {
+            entries.push(Entry {
+                path: path.to_string(),
+                pointer,
+                source: text.to_string(),
+                translation: String::new(),
+            });
+        }
+    };
+
+    match value {
+        SBType::Object(map) => {
+            for (key, value) in map {
+                let pointer = format!("{}/{}", pointer, escape_pointer(key));
+                match value {
+                    SBType::String(text) if keys.contains(&key.as_str()) => {
+                        push(entries, pointer, text)
+                    }
+                    // Codex pages are a plain list of strings
+                    SBType::Array(array)
+                        if keys.contains(&key.as_str())
+                            && array.iter().all(|v| matches!(v, SBType::String(_))) =>
+                    {
+                        for (i, text) in array.iter().filter_map(SBType::as_str).enumerate() {
+                            push(entries, format!("{}/{}", pointer, i), text);
+                        }
+                    }
+                    value => collect(value, &pointer, keys, path, entries),
+                }
+            }
+        }
+        SBType::Array(array) => {
+            for (i, value) in array.iter().enumerate() {
+                collect(value, &format!("{}/{}", pointer, i), keys, path, entries);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub(super) fn extract(reader: &dyn AssetReader) -> Vec<Entry> {
+    let mut paths: Vec<String> = reader
+        .paths()
+        .into_iter()
+        // Patch operations point into other assets, their text isn't their own
+        .filter(|path| path::maybe_json(path) && !path.ends_with(".patch"))
+        .cloned()
+        .collect();
+    paths.sort();
+
+    let mut entries = Vec::new();
+    for path in paths {
+        // Assets that aren't valid JSON have nothing to translate
+        let Ok(value) = reader.file(&path).and_then(|file| file.as_json()) else {
+            continue;
+        };
+        let extension = path.rsplit_once('.').map(|(_, e)| e).unwrap_or_default();
+        collect(&value, "", text_keys(extension), &path, &mut entries);
+    }
+    entries
+}
+
+fn po_quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn po_unquote(text: &str, line: usize) -> anyhow::Result<String> {
+    let Some(inner) = text
+        .trim()
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+    else {
+        anyhow::bail!("Expected quoted string at line {}", line);
+    };
+
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unquoted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unquoted.push('\n'),
+            Some('t') => unquoted.push('\t'),
+            Some('r') => unquoted.push('\r'),
+            Some(c @ ('\\' | '"')) => unquoted.push(c),
+            _ => anyhow::bail!("Invalid escape sequence at line {}", line),
+        }
+    }
+    Ok(unquoted)
+}
+
+pub fn write_catalog(entries: &[Entry], format: CatalogFormat) -> String {
+    match format {
+        CatalogFormat::Json => {
+            let mut catalog: IndexMap<String, SBType> = IndexMap::new();
+            for entry in entries {
+                let strings = catalog
+                    .entry(entry.path.clone())
+                    .or_insert_with(|| SBType::Object(IndexMap::new()));
+                if let SBType::Object(strings) = strings {
+                    let mut text = IndexMap::new();
+                    text.insert("source".to_string(), SBType::String(entry.source.clone()));
+                    text.insert(
+                        "translation".to_string(),
+                        SBType::String(entry.translation.clone()),
+                    );
+                    strings.insert(entry.pointer.clone(), SBType::Object(text));
+                }
+            }
+            jsonc::stringify(&SBType::Object(catalog), Some(2))
+        }
+        CatalogFormat::Po => {
+            let mut output =
+                String::from("msgid \"\"\nmsgstr \"Content-Type: text/plain; charset=UTF-8\\n\"\n");
+            for entry in entries {
+                output.push_str(&format!(
+                    "\n#: {}\nmsgctxt {}\nmsgid {}\nmsgstr {}\n",
+                    entry.path,
+                    po_quote(&format!("{}:{}", entry.path, entry.pointer)),
+                    po_quote(&entry.source),
+                    po_quote(&entry.translation)
+                ));
+            }
+            output
+        }
+    }
+}
+
+fn read_po(source: &str) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut fields: IndexMap<&str, String> = IndexMap::new();
+    let mut current: Option<&str> = None;
+
+    let mut flush = |fields: &mut IndexMap<&str, String>| -> anyhow::Result<()> {
+        if let Some(context) = fields.get("msgctxt") {
+            let Some((path, pointer)) = context.split_once(':') else {
+                anyhow::bail!("Invalid message context '{}'", context);
+            };
+            entries.push(Entry {
+                path: path.to_string(),
+                pointer: pointer.to_string(),
+                source: fields.get("msgid").cloned().unwrap_or_default(),
+                translation: fields.get("msgstr").cloned().unwrap_or_default(),
+            });
+        }
+        fields.clear();
+        Ok(())
+    };
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            flush(&mut fields)?;
+            current = None;
+        } else if line.starts_with('#') {
+            continue;
+        } else if line.starts_with('"') {
+            let Some(field) = current else {
+                anyhow::bail!("Unexpected string at line {}", line_number);
+            };
+            let text = po_unquote(line, line_number)?;
+            fields.entry(field).or_default().push_str(&text);
+        } else {
+            let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let field = match keyword {
+                "msgctxt" => "msgctxt",
+                "msgid" => "msgid",
+                "msgstr" => "msgstr",
+                _ => anyhow::bail!("Unknown keyword '{}' at line {}", keyword, line_number),
+            };
+            // A new context or id without a blank line starts the next entry
+            if field != "msgstr" && fields.contains_key("msgstr") {
+                flush(&mut fields)?;
+            }
+            fields.insert(field, po_unquote(rest, line_number)?);
+            current = Some(field);
+        }
+    }
+    flush(&mut fields)?;
+
+    Ok(entries)
+}
+
+fn read_json(source: &str) -> anyhow::Result<Vec<Entry>> {
+    let SBType::Object(catalog) = jsonc::parse(source)? else {
+        anyhow::bail!("Catalog is not an object");
+    };
+
+    let mut entries = Vec::new();
+    for (path, strings) in catalog {
+        let SBType::Object(strings) = strings else {
+            anyhow::bail!("Catalog entry '{}' is not an object", path);
+        };
+        for (pointer, text) in strings {
+            let field = |key: &str| {
+                text.get(key)
+                    .and_then(SBType::as_str)
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            entries.push(Entry {
+                path: path.clone(),
+                pointer,
+                source: field("source"),
+                translation: field("translation"),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+pub fn read_catalog(source: &str, format: CatalogFormat) -> anyhow::Result<Vec<Entry>> {
+    match format {
+        CatalogFormat::Json => read_json(source),
+        CatalogFormat::Po => read_po(source),
+    }
+}
+
+// Writes one `.patch` file per translated asset, returns the number of patch files
+pub fn write_patches(
+    entries: &[Entry],
+    output: &str,
+    metadata: Option<&SBType>,
+) -> anyhow::Result<usize> {
+    let mut patches: IndexMap<&str, Vec<SBType>> = IndexMap::new();
+    for entry in entries {
+        // Untranslated strings keep the original text
+        if entry.translation.is_empty() || entry.translation == entry.source {
+            continue;
+        }
+
+        let mut operation = IndexMap::new();
+        operation.insert("op".to_string(), SBType::String("replace".to_string()));
+        operation.insert("path".to_string(), SBType::String(entry.pointer.clone()));
+        operation.insert(
+            "value".to_string(),
+            SBType::String(entry.translation.clone()),
+        );
+        patches
+            .entry(&entry.path)
+            .or_default()
+            .push(SBType::Object(operation));
+    }
+    patches.sort_keys();
+
+    let output = Path::new(output);
+    fs::create_dir_all(output)?;
+    if let Some(metadata) = metadata {
+        fs::write(
+            output.join("_metadata"),
+            jsonc::stringify(metadata, Some(2)),
+        )?;
+    }
+
+    for (path, operations) in &patches {
+        if path.split('/').any(|part| part == "..") {
+            anyhow::bail!("Invalid asset path '{}'", path);
+        }
+        let target = output.join(format!("{}.patch", path.trim_start_matches('/')));
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            target,
+            jsonc::stringify(&SBType::Array(operations.clone()), Some(2)),
+        )?;
+    }
+
+    Ok(patches.len())
+}
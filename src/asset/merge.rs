@@ -0,0 +1,53 @@
+use indexmap::IndexMap;
+
+use super::SBType;
+
+const MERGE_KEY: &str = "__merge";
+
+// Drops `__merge` directives from a value that has nothing left to merge into
+fn strip(value: &SBType) -> SBType {
+    match value {
+        SBType::Object(map) => SBType::Object(
+            map.iter()
+                .filter(|(key, _)| *key != MERGE_KEY)
+                .map(|(key, value)| (key.clone(), strip(value)))
+                .collect(),
+        ),
+        SBType::Array(array) => SBType::Array(array.iter().map(strip).collect()),
+        value => value.clone(),
+    }
+}
+
+// The game's jsonMerge: objects merge key by key, null keeps the base and anything else
+// replaces it. An object with `"__merge": false` replaces the base object instead of merging.
+pub fn merge(base: &SBType, merger: &SBType) -> SBType {
+    match (base, merger) {
+        (SBType::Object(base), SBType::Object(merger)) => {
+            if let Some(SBType::Boolean(false)) = merger.get(MERGE_KEY) {
+                return strip(&SBType::Object(merger.clone()));
+            }
+
+            let mut merged: IndexMap<String, SBType> = base.clone();
+            for (key, value) in merger {
+                if key == MERGE_KEY {
+                    continue;
+                }
+                let value = match merged.get(key) {
+                    Some(existing) => merge(existing, value),
+                    None => strip(value),
+                };
+                merged.insert(key.clone(), value);
+            }
+            SBType::Object(merged)
+        }
+        (base, SBType::Nil) => base.clone(),
+        (_, merger) => strip(merger),
+    }
+}
+
+// Merges every value into the first, left to right
+pub fn merge_all<'a>(values: impl IntoIterator<Item = &'a SBType>) -> SBType {
+    values
+        .into_iter()
+        .fold(SBType::Nil, |merged, value| merge(&merged, value))
+}
@@ -1,14 +1,30 @@
+mod animation;
+mod archive;
+mod atlas;
+mod audio;
+mod cache;
 mod directory;
+mod dungeon;
 mod file;
+mod frames;
+mod ids;
+pub(crate) mod jsonc;
+mod loader;
+mod localization;
+mod merge;
 mod packet;
+mod path;
 mod reader;
+mod recipe;
+mod scripts;
 mod vlq;
 mod writer;
 
-use std::collections::HashMap;
 use std::io::Cursor;
+use std::sync::Arc;
 
-use mlua::IntoLua;
+use indexmap::IndexMap;
+use mlua::{FromLua, IntoLua};
 
 use file::AssetFile;
 use packet::PacketReader;
@@ -21,7 +37,46 @@ pub enum SBType {
     Int(i64),
     String(String),
     Array(Vec<SBType>),
-    Object(HashMap<String, SBType>),
+    Object(IndexMap<String, SBType>),
+}
+
+impl SBType {
+    pub fn get(&self, key: &str) -> Option<&SBType> {
+        match self {
+            SBType::Object(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            SBType::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            SBType::Float(v) => Some(*v),
+            SBType::Int(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            SBType::Int(v) => Some(*v),
+            SBType::Float(v) if v.fract() == 0.0 => Some(*v as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<SBType>> {
+        match self {
+            SBType::Array(array) => Some(array),
+            _ => None,
+        }
+    }
 }
 
 impl TryFrom<SBType> for u8 {
@@ -49,18 +104,23 @@ impl TryFrom<json::JsonValue> for SBType {
             json::JsonValue::Short(short) => Ok(SBType::String(short.to_string())),
             json::JsonValue::String(string) => Ok(SBType::String(string)),
             json::JsonValue::Number(number) => {
-                let num: f64  = number.into();
-                if num.fract() == 0.0 {
-                    Ok(SBType::Int(num as i64))
-                } else {
-                    Ok(SBType::Float(num))
+                // Only literals without a fraction or exponent are integers, the ones outside
+                // i64 fall back to a float like the game does
+                let (positive, mantissa, exponent) = number.as_parts();
+                let int = i64::try_from(mantissa)
+                    .ok()
+                    .map(|v| if positive { v } else { -v })
+                    .or((!positive && mantissa == 1 << 63).then_some(i64::MIN));
+                match int {
+                    Some(int) if exponent == 0 => Ok(SBType::Int(int)),
+                    _ => Ok(SBType::Float(number.into())),
                 }
             }
             json::JsonValue::Boolean(boolean) => {
                 Ok(SBType::Boolean(boolean))
             },
             json::JsonValue::Object(object) => {
-                let mut map = HashMap::new();
+                let mut map = IndexMap::new();
                 for (key, value) in object.iter() {
                     map.insert(key.to_string(), SBType::try_from(value.clone())?);
                 }
@@ -103,29 +163,93 @@ impl IntoLua for SBType {
     }
 }
 
+impl FromLua for SBType {
+    fn from_lua(value: mlua::Value, _: &mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            mlua::Value::Nil => Ok(SBType::Nil),
+            mlua::Value::Boolean(v) => Ok(SBType::Boolean(v)),
+            mlua::Value::Integer(v) => Ok(SBType::Int(v)),
+            mlua::Value::Number(v) => Ok(SBType::Float(v)),
+            mlua::Value::String(v) => Ok(SBType::String(v.to_str()?.to_string())),
+            mlua::Value::Table(table) => {
+                // Tables whose keys are exactly 1..n are arrays, anything else is an object
+                let length = table.raw_len();
+                if table.pairs::<mlua::Value, mlua::Value>().count() == length {
+                    let array = table
+                        .sequence_values::<SBType>()
+                        .collect::<mlua::Result<Vec<SBType>>>()?;
+                    Ok(SBType::Array(array))
+                } else {
+                    let mut map = IndexMap::new();
+                    for pair in table.pairs::<mlua::Value, SBType>() {
+                        let (key, value) = pair?;
+                        let key = match key {
+                            mlua::Value::String(key) => key.to_str()?.to_string(),
+                            key => key.to_string()?,
+                        };
+                        map.insert(key, value);
+                    }
+                    // Lua iteration order is arbitrary, sort so the result is stable
+                    map.sort_keys();
+                    Ok(SBType::Object(map))
+                }
+            }
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "SBType".to_string(),
+                message: Some("Unsupported value type".to_string()),
+            }),
+        }
+    }
+}
+
 trait AssetReader {
-    fn file(&mut self, path: &str) -> anyhow::Result<AssetFile>;
+    fn file(&self, path: &str) -> anyhow::Result<AssetFile>;
 
     fn exist(&self, path: &str) -> bool;
 
     fn paths(&self) -> Vec<&String>;
 
     fn meta(&self, key: String) -> anyhow::Result<SBType>;
+
+    fn metadata(&self) -> SBType;
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone)]
+pub(crate) enum AssetReaderEnum {
+    PacketReader(Arc<PacketReader<Cursor<Vec<u8>>>>),
+    DirectoryReader(Arc<directory::DirectoryReader>),
+    ZipReader(Arc<archive::ZipReader>),
 }
 
-enum AssetReaderEnum {
-    PacketReader(PacketReader<Cursor<Vec<u8>>>),
-    DirectoryReader(directory::DirectoryReader),
+impl AssetReaderEnum {
+    fn as_reader(&self) -> &dyn AssetReader {
+        match self {
+            AssetReaderEnum::PacketReader(reader) => reader.as_ref(),
+            AssetReaderEnum::DirectoryReader(reader) => reader.as_ref(),
+            AssetReaderEnum::ZipReader(reader) => reader.as_ref(),
+        }
+    }
+
+    pub(crate) fn read_bytes(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        self.as_reader().file(path).map(|file| file.bytes)
+    }
+
+    pub(crate) fn exist(&self, path: &str) -> bool {
+        self.as_reader().exist(path)
+    }
 }
 
 impl mlua::UserData for AssetReaderEnum {
     fn add_fields<F: mlua::UserDataFields<Self>>(_: &mut F) {}
 
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_method_mut("file", |_, this, path: String| {
+        methods.add_method("file", |_, this, path: String| {
             match this {
                 AssetReaderEnum::PacketReader(reader) => reader.file(&path),
                 AssetReaderEnum::DirectoryReader(reader) => reader.file(&path),
+                AssetReaderEnum::ZipReader(reader) => reader.file(&path),
             }
             .map_err(|e| e.into())
         });
@@ -133,6 +257,7 @@ impl mlua::UserData for AssetReaderEnum {
         methods.add_method("exist", |_, this, path: String| match this {
             AssetReaderEnum::PacketReader(reader) => Ok(reader.exist(&path)),
             AssetReaderEnum::DirectoryReader(reader) => Ok(reader.exist(&path)),
+            AssetReaderEnum::ZipReader(reader) => Ok(reader.exist(&path)),
         });
 
         methods.add_method("paths", |_, this, _: ()| match this {
@@ -142,15 +267,81 @@ impl mlua::UserData for AssetReaderEnum {
             AssetReaderEnum::DirectoryReader(reader) => {
                 Ok(reader.paths().into_iter().cloned().collect::<Vec<String>>())
             }
+            AssetReaderEnum::ZipReader(reader) => {
+                Ok(reader.paths().into_iter().cloned().collect::<Vec<String>>())
+            }
         });
 
         methods.add_method("meta", |_, this, key: String| {
             match this {
                 AssetReaderEnum::PacketReader(reader) => reader.meta(key),
                 AssetReaderEnum::DirectoryReader(reader) => reader.meta(key),
+                AssetReaderEnum::ZipReader(reader) => reader.meta(key),
             }
             .map_err(|e| e.into())
         });
+
+        methods.add_method("stale", |_, this, _: ()| {
+            cache::is_stale(this).map_err(|e| e.into())
+        });
+
+        methods.add_method(
+            "export_archive",
+            |_, this, (path, format): (String, Option<String>)| {
+                let format = archive::ArchiveFormat::try_from(format.as_deref().unwrap_or("zip"))?;
+                archive::export(this.as_reader(), &path, format).map_err(|e| e.into())
+            },
+        );
+
+        methods.add_method("recipe_graph", |_, this, _: ()| {
+            recipe::RecipeGraph::build(this.as_reader()).map_err(|e| e.into())
+        });
+
+        methods.add_method("script_graph", |_, this, _: ()| {
+            Ok(scripts::ScriptGraph::build(this.as_reader()))
+        });
+
+        methods.add_method("animation", |_, this, path: String| {
+            animation::Animation::load(this.as_reader(), &path)
+                .map(|animation| SBType::from(&animation))
+                .map_err(|e| e.into())
+        });
+
+        methods.add_method("validate_animation", |_, this, path: String| {
+            let reader = this.as_reader();
+            let animation = animation::Animation::load(reader, &path)?;
+            Ok(SBType::from(&animation.validate(reader)))
+        });
+
+        methods.add_method("extract_strings", |_, this, output: String| {
+            let entries = localization::extract(this.as_reader());
+            let format = localization::CatalogFormat::from_path(&output);
+            std::fs::write(&output, localization::write_catalog(&entries, format))?;
+            Ok(entries.len())
+        });
+
+        methods.add_method("dungeon", |_, this, path: String| {
+            dungeon::Dungeon::load(this.as_reader(), &path)
+                .map(|dungeon| SBType::from(&dungeon))
+                .map_err(|e| e.into())
+        });
+
+        methods.add_method(
+            "render_dungeon",
+            |lua, this, (path, part, output): (String, String, String)| {
+                let reader = this.as_reader();
+                let dungeon = dungeon::Dungeon::load(reader, &path)?;
+                let mut renderer = dungeon::DungeonRenderer::new(reader);
+                let image = renderer.render_part(&dungeon, &part)?;
+                std::fs::write(&output, image.to_png()?)?;
+
+                let table = lua.create_table()?;
+                table.set("width", image.width)?;
+                table.set("height", image.height)?;
+                table.set("warnings", renderer.warnings)?;
+                Ok(table)
+            },
+        );
     }
 }
 
@@ -158,18 +349,81 @@ pub fn register_asset(lua: &mlua::Lua) -> mlua::Result<mlua::Table> {
     let asset = lua.create_table()?;
 
     let asset_reader = lua.create_function(|_, path: String| -> mlua::Result<AssetReaderEnum> {
-        if path.ends_with(".pak") {
-            let cursor = Cursor::new(std::fs::read(path)?);
-            let packet_reader = PacketReader::new(cursor);
-            Ok(AssetReaderEnum::PacketReader(packet_reader?))
-        } else {
-            let directory_reader =
-                directory::DirectoryReader::new(&path);
-            Ok(AssetReaderEnum::DirectoryReader(directory_reader?))
-        }
+        cache::open(&path).map_err(|e| e.into())
     })?;
 
     asset.set("AssetReader", asset_reader)?;
 
+    let open_async = lua.create_function(|_, path: String| -> mlua::Result<loader::PendingReader> {
+        Ok(loader::PendingReader::spawn(path))
+    })?;
+
+    asset.set("open_async", open_async)?;
+
+    let set_pak_metadata = lua.create_function(
+        |_, (path, metadata): (String, SBType)| -> mlua::Result<()> {
+            match metadata {
+                SBType::Object(map) => packet::set_metadata(&path, map).map_err(|e| e.into()),
+                SBType::Array(array) if array.is_empty() => Ok(()),
+                _ => Err(mlua::Error::external("Expected metadata table")),
+            }
+        },
+    )?;
+
+    asset.set("set_pak_metadata", set_pak_metadata)?;
+
+    let translation_patch = lua.create_function(
+        |_, (catalog, output, metadata): (String, String, Option<SBType>)| -> mlua::Result<usize> {
+            let source = std::fs::read_to_string(&catalog)?;
+            let format = localization::CatalogFormat::from_path(&catalog);
+            let entries = localization::read_catalog(&source, format)?;
+            localization::write_patches(&entries, &output, metadata.as_ref()).map_err(|e| e.into())
+        },
+    )?;
+
+    asset.set("translation_patch", translation_patch)?;
+
+    let pack_sprites = lua.create_function(
+        |lua, (frames, output, options): (SBType, String, Option<SBType>)| {
+            let options = match &options {
+                Some(options) => atlas::PackOptions::try_from(options)?,
+                None => atlas::PackOptions::default(),
+            };
+            let frames = atlas::load_frames(&frames)?;
+            let sheet = atlas::pack(&frames, &options)?;
+            let frames_path = atlas::write(&sheet, &output)?;
+
+            let table = lua.create_table()?;
+            table.set("width", sheet.image.width)?;
+            table.set("height", sheet.image.height)?;
+            table.set("frames", frames_path)?;
+            Ok(table)
+        },
+    )?;
+
+    asset.set("pack_sprites", pack_sprites)?;
+
+    let id_report = lua.create_function(
+        |_, (readers, options): (Vec<mlua::AnyUserData>, Option<SBType>)| {
+            let readers = readers
+                .iter()
+                .map(|reader| reader.borrow::<AssetReaderEnum>().map(|r| r.clone()))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            let layers: Vec<&dyn AssetReader> = readers.iter().map(|r| r.as_reader()).collect();
+
+            let option = |key: &str| options.as_ref().and_then(|o| o.get(key)).and_then(SBType::as_i64);
+            let report = ids::IdReport::build(&layers);
+            Ok(report.to_sbtype(option("free_size").unwrap_or(1), option("free_limit").unwrap_or(10) as usize))
+        },
+    )?;
+
+    asset.set("id_report", id_report)?;
+
+    let json_merge = lua.create_function(|_, values: mlua::Variadic<SBType>| {
+        Ok(merge::merge_all(values.iter()))
+    })?;
+
+    asset.set("json_merge", json_merge)?;
+
     Ok(asset)
 }
@@ -1,12 +1,17 @@
-use std::collections::HashMap;
-use std::io::{Seek, SeekFrom};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
 
-use byteorder::{BigEndian, ReadBytesExt};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use indexmap::IndexMap;
 
 use super::SBType;
 use super::AssetReader;
 use super::file::AssetFile;
+use super::loader::LoadProgress;
 use super::reader::SBReader;
+use super::writer::SBWriter;
 
 const ASSET_HEADER: [u8; 8] = *b"SBAsset6";
 const INDEX_HEADER: [u8; 5] = *b"INDEX";
@@ -16,15 +21,15 @@ pub struct PacketReader<R>
 where R: SBReader + Seek
 {
     // (offset, length)
-    index: HashMap<String, (u64, u64)>,
-    metadata: HashMap<String, SBType>,
-    buffer: R
+    index: IndexMap<String, (u64, u64)>,
+    metadata: IndexMap<String, SBType>,
+    buffer: Mutex<R>,
 }
 
 impl<R> PacketReader<R>
 where R: SBReader + Seek
 {
-    pub fn new(mut input: R) -> anyhow::Result<Self> {
+    pub fn new(mut input: R, progress: Option<&LoadProgress>) -> anyhow::Result<Self> {
         let mut magic = [0u8; 8];
 
         input.read_exact(&mut magic)?;
@@ -44,9 +49,17 @@ where R: SBReader + Seek
         }
 
         let metadata = input.read_map()?;
-        let mut index = HashMap::new();
+        let mut index = IndexMap::new();
 
-        for _ in 0..input.read_vlq_u64()? {
+        let entries = input.read_vlq_u64()?;
+        if let Some(progress) = progress {
+            progress.total_entries.store(entries, Ordering::Relaxed);
+        }
+
+        for _ in 0..entries {
+            if let Some(progress) = progress {
+                progress.add_entry();
+            }
             index.insert(
                 input.read_string()?,
                 (
@@ -56,8 +69,91 @@ where R: SBReader + Seek
             );
         }
 
-        Ok(Self { index, metadata, buffer: input })
+        Ok(Self { index, metadata, buffer: Mutex::new(input) })
+    }
+}
+
+// Writes `index` at `position` and points the header at it once it is on disk, so a crash
+// before that leaves the previous index in charge
+fn write_index_at(file: &mut File, position: u64, index: &[u8]) -> anyhow::Result<()> {
+    file.seek(SeekFrom::Start(position))?;
+    file.write_all(index)?;
+    file.sync_data()?;
+    file.seek(SeekFrom::Start(ASSET_HEADER.len() as u64))?;
+    file.write_u64::<BigEndian>(position)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+// Merges `metadata` into the pak's metadata, rewriting only the INDEX block. The live index is
+// never overwritten: the new one goes to free space and the header is switched over last
+pub fn set_metadata(pak_path: &str, metadata: IndexMap<String, SBType>) -> anyhow::Result<()> {
+    let file = OpenOptions::new().read(true).write(true).open(pak_path)?;
+    let file_length = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+
+    if magic != ASSET_HEADER {
+        anyhow::bail!("Invalid packed file header");
+    }
+
+    let index_start = reader.read_u64::<BigEndian>()?;
+    reader.seek(SeekFrom::Start(index_start))?;
+
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+
+    if header != INDEX_HEADER {
+        anyhow::bail!("Invalid index header");
+    }
+
+    let mut new_metadata = reader.read_map()?;
+    new_metadata.extend(metadata);
+
+    // Keep the entries in their original order
+    let mut entries = Vec::new();
+    for _ in 0..reader.read_vlq_u64()? {
+        entries.push((
+            reader.read_string()?,
+            reader.read_u64::<BigEndian>()?,
+            reader.read_u64::<BigEndian>()?,
+        ));
+    }
+    let mut file = reader.into_inner();
+
+    // Everything between the last file and the live index is unused
+    let data_end = entries
+        .iter()
+        .map(|(_, offset, length)| offset + length)
+        .fold(ASSET_HEADER.len() as u64 + 8, u64::max);
+
+    let mut index = Vec::new();
+    index.write_all(&INDEX_HEADER)?;
+    index.write_map(new_metadata)?;
+    index.write_vlq_u64(entries.len() as u64)?;
+    for (path, offset, length) in entries {
+        index.write_string(&path)?;
+        index.write_u64::<BigEndian>(offset)?;
+        index.write_u64::<BigEndian>(length)?;
     }
+    let length = index.len() as u64;
+
+    let mut live = index_start;
+    // Too large for the gap before the live index, append it first
+    if data_end + length > live {
+        write_index_at(&mut file, file_length, &index)?;
+        live = file_length;
+    }
+    // Move it down right after the data, so repeated edits don't grow the pak
+    if data_end + length <= live {
+        write_index_at(&mut file, data_end, &index)?;
+        live = data_end;
+    }
+    file.set_len(live + length)?;
+    file.sync_all()?;
+    Ok(())
 }
 
 impl<R> AssetReader for PacketReader<R>
@@ -67,7 +163,7 @@ where R: SBReader + Seek
         self.index.contains_key(path)
     }
     
-    fn file(&mut self, path: &str) -> anyhow::Result<AssetFile> {
+    fn file(&self, path: &str) -> anyhow::Result<AssetFile> {
         if !self.exist(path) {
             anyhow::bail!("File is not exist")
         }
@@ -76,9 +172,13 @@ where R: SBReader + Seek
         let info = self.index[&path];
 
         let mut bytes = vec![0u8; info.1 as usize];
-        self.buffer.seek(SeekFrom::Start(info.0))?;
+        let mut buffer = self
+            .buffer
+            .lock()
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        buffer.seek(SeekFrom::Start(info.0))?;
 
-        self.buffer.read_exact(&mut bytes)?;
+        buffer.read_exact(&mut bytes)?;
 
         Ok(AssetFile { path, bytes })
     }
@@ -88,6 +188,13 @@ where R: SBReader + Seek
     }
 
     fn meta(&self, key: String) -> anyhow::Result<SBType> {
-        Ok(self.metadata[&key].clone())
+        match self.metadata.get(&key) {
+            Some(value) => Ok(value.clone()),
+            None => anyhow::bail!("Key '{}' not found in metadata", key),
+        }
+    }
+
+    fn metadata(&self) -> SBType {
+        SBType::Object(self.metadata.clone())
     }
 }
\ No newline at end of file
@@ -0,0 +1,48 @@
+// Directory part of an asset path, keeping the trailing slash
+pub fn directory(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(index) => &path[..=index],
+        None => "/",
+    }
+}
+
+// Resolves `relative` against the asset at `base`, the way the game resolves paths inside
+// JSON assets: absolute paths are kept, anything else is relative to the asset's directory
+pub fn resolve(base: &str, relative: &str) -> String {
+    let joined = if relative.starts_with('/') {
+        relative.to_string()
+    } else {
+        format!("{}{}", directory(base), relative)
+    };
+
+    let mut parts: Vec<&str> = Vec::new();
+    for part in joined.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+// Drops the `:frame` and `?directives` suffixes from an image reference
+pub fn image_file(path: &str) -> &str {
+    let end = path.find(['?', ':']).unwrap_or(path.len());
+    &path[..end]
+}
+
+// Files that are never JSON, everything else with an extension is worth trying. `.frames` and
+// `.patch` files are JSON too
+const NON_JSON_EXTENSIONS: [&str; 9] =
+    ["png", "ogg", "wav", "mp3", "lua", "ttf", "otf", "txt", "md"];
+
+pub fn maybe_json(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or_default();
+    match name.rsplit_once('.') {
+        Some((_, extension)) => !NON_JSON_EXTENSIONS.contains(&extension),
+        None => false,
+    }
+}
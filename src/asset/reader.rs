@@ -1,8 +1,8 @@
-use std::collections::HashMap;
 use std::io::Read;
 
 use anyhow::Ok;
 use byteorder::{BigEndian, ReadBytesExt};
+use indexmap::IndexMap;
 
 use super::SBType;
 use super::vlq::{VLQi64, VLQu64};
@@ -39,8 +39,8 @@ pub trait SBReader: Read + Sized {
         Ok(array)
     }
 
-    fn read_map(&mut self) -> anyhow::Result<HashMap<String, SBType>> {
-        let mut map = HashMap::new();
+    fn read_map(&mut self) -> anyhow::Result<IndexMap<String, SBType>> {
+        let mut map = IndexMap::new();
         let length = self.read_vlq_u64()?;
 
         for _ in 0..length {
@@ -66,4 +66,6 @@ pub trait SBReader: Read + Sized {
     }
 }
 
-impl SBReader for std::io::Cursor<Vec<u8>> {}
\ No newline at end of file
+impl SBReader for std::io::Cursor<Vec<u8>> {}
+
+impl SBReader for std::io::BufReader<std::fs::File> {}
\ No newline at end of file
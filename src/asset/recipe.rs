@@ -0,0 +1,401 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::AssetReader;
+use super::SBType;
+
+// Asset extensions that define an item, with the key holding the item name
+const ITEM_EXTENSIONS: &[(&str, &str)] = &[
+    (".item", "itemName"),
+    (".object", "objectName"),
+    (".activeitem", "itemName"),
+    (".consumable", "itemName"),
+    (".matitem", "itemName"),
+    (".liqitem", "itemName"),
+    (".augment", "itemName"),
+    (".currency", "itemName"),
+    (".instrument", "itemName"),
+    (".thrownitem", "itemName"),
+    (".unlock", "itemName"),
+    (".head", "itemName"),
+    (".chest", "itemName"),
+    (".legs", "itemName"),
+    (".back", "itemName"),
+    (".beamaxe", "itemName"),
+    (".flashlight", "itemName"),
+    (".miningtool", "itemName"),
+    (".harvestingtool", "itemName"),
+    (".painttool", "itemName"),
+    (".wiretool", "itemName"),
+    (".inspectiontool", "itemName"),
+    (".tillingtool", "itemName"),
+];
+
+#[derive(Debug, Clone)]
+pub struct ItemCount {
+    pub item: String,
+    pub count: u64,
+}
+
+impl ItemCount {
+    // Accepts "name", ["name", count] and {"item"/"name": ..., "count": ...}
+    pub fn parse(value: &SBType) -> anyhow::Result<Self> {
+        let (item, count) = match value {
+            SBType::String(name) => (Some(name.as_str()), None),
+            SBType::Array(array) => (
+                array.first().and_then(SBType::as_str),
+                array.get(1).and_then(SBType::as_i64),
+            ),
+            SBType::Object(_) => (
+                value
+                    .get("item")
+                    .or_else(|| value.get("name"))
+                    .and_then(SBType::as_str),
+                value.get("count").and_then(SBType::as_i64),
+            ),
+            _ => (None, None),
+        };
+
+        match item {
+            Some(item) => Ok(Self {
+                item: item.to_string(),
+                count: count.unwrap_or(1).max(0) as u64,
+            }),
+            None => anyhow::bail!("Invalid item descriptor: {:?}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Recipe {
+    pub path: String,
+    pub inputs: Vec<ItemCount>,
+    pub currency_inputs: Vec<ItemCount>,
+    pub output: ItemCount,
+    pub groups: Vec<String>,
+}
+
+impl Recipe {
+    pub fn parse(path: &str, value: &SBType) -> anyhow::Result<Self> {
+        let inputs = value
+            .get("input")
+            .and_then(SBType::as_array)
+            .map(|array| array.iter().map(ItemCount::parse).collect())
+            .unwrap_or(Ok(Vec::new()))?;
+
+        let currency_inputs = match value.get("currencyInputs") {
+            Some(SBType::Object(map)) => map
+                .iter()
+                .map(|(currency, count)| ItemCount {
+                    item: currency.clone(),
+                    count: count.as_i64().unwrap_or(0).max(0) as u64,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let output = match value.get("output") {
+            Some(output) => ItemCount::parse(output)?,
+            None => anyhow::bail!("Recipe has no output"),
+        };
+
+        let groups = value
+            .get("groups")
+            .and_then(SBType::as_array)
+            .map(|array| {
+                array
+                    .iter()
+                    .filter_map(SBType::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            path: path.to_string(),
+            inputs,
+            currency_inputs,
+            output,
+            groups,
+        })
+    }
+}
+
+impl From<&ItemCount> for SBType {
+    fn from(value: &ItemCount) -> Self {
+        SBType::Object(
+            [
+                ("item".to_string(), SBType::String(value.item.clone())),
+                ("count".to_string(), SBType::Int(value.count as i64)),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
+impl From<&Recipe> for SBType {
+    fn from(value: &Recipe) -> Self {
+        let list = |items: &Vec<ItemCount>| SBType::Array(items.iter().map(SBType::from).collect());
+        SBType::Object(
+            [
+                ("path".to_string(), SBType::String(value.path.clone())),
+                ("input".to_string(), list(&value.inputs)),
+                ("currencyInputs".to_string(), list(&value.currency_inputs)),
+                ("output".to_string(), SBType::from(&value.output)),
+                (
+                    "groups".to_string(),
+                    SBType::Array(value.groups.iter().cloned().map(SBType::String).collect()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RawCost {
+    pub materials: BTreeMap<String, f64>,
+    pub currencies: BTreeMap<String, f64>,
+    pub cycles: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MissingItem {
+    pub recipe: String,
+    pub item: String,
+    pub role: &'static str,
+}
+
+#[derive(Debug, Default)]
+pub struct RecipeGraph {
+    recipes: Vec<Recipe>,
+    items: HashSet<String>,
+    by_input: HashMap<String, Vec<usize>>,
+    by_output: HashMap<String, Vec<usize>>,
+    by_group: HashMap<String, Vec<usize>>,
+    // (path, error) for assets that could not be parsed
+    errors: Vec<(String, String)>,
+}
+
+impl RecipeGraph {
+    pub fn build(reader: &dyn AssetReader) -> anyhow::Result<Self> {
+        let mut graph = Self::default();
+
+        let mut paths: Vec<String> = reader.paths().into_iter().cloned().collect();
+        paths.sort();
+
+        for path in paths {
+            let name_key = ITEM_EXTENSIONS
+                .iter()
+                .find(|(extension, _)| path.ends_with(extension))
+                .map(|(_, key)| *key);
+            let is_recipe = path.ends_with(".recipe");
+            let is_codex = path.ends_with(".codex");
+            if name_key.is_none() && !is_recipe && !is_codex {
+                continue;
+            }
+
+            let value = match reader.file(&path).and_then(|file| file.as_json()) {
+                Ok(value) => value,
+                Err(e) => {
+                    graph.errors.push((path, e.to_string()));
+                    continue;
+                }
+            };
+
+            if is_recipe {
+                match Recipe::parse(&path, &value) {
+                    Ok(recipe) => graph.add_recipe(recipe),
+                    Err(e) => graph.errors.push((path, e.to_string())),
+                }
+            } else if is_codex {
+                // Codex items are named after their id
+                if let Some(id) = value.get("id").and_then(SBType::as_str) {
+                    graph.items.insert(format!("{}-codex", id));
+                }
+            } else if let Some(name) = name_key
+                .and_then(|key| value.get(key))
+                .and_then(SBType::as_str)
+            {
+                graph.items.insert(name.to_string());
+            }
+        }
+
+        Ok(graph)
+    }
+
+    pub fn add_recipe(&mut self, recipe: Recipe) {
+        let index = self.recipes.len();
+        for input in &recipe.inputs {
+            let entry = self.by_input.entry(input.item.clone()).or_default();
+            if !entry.contains(&index) {
+                entry.push(index);
+            }
+        }
+        self.by_output
+            .entry(recipe.output.item.clone())
+            .or_default()
+            .push(index);
+        for group in &recipe.groups {
+            self.by_group.entry(group.clone()).or_default().push(index);
+        }
+        self.recipes.push(recipe);
+    }
+
+    fn select(&self, indices: Option<&Vec<usize>>) -> Vec<&Recipe> {
+        indices
+            .map(|indices| indices.iter().map(|&i| &self.recipes[i]).collect())
+            .unwrap_or_default()
+    }
+
+    // Recipes that take `item` as one of their inputs
+    pub fn craftable_from(&self, item: &str) -> Vec<&Recipe> {
+        self.select(self.by_input.get(item))
+    }
+
+    // Recipes that produce `item`
+    pub fn recipes_for(&self, item: &str) -> Vec<&Recipe> {
+        self.select(self.by_output.get(item))
+    }
+
+    pub fn group(&self, group: &str) -> Vec<&Recipe> {
+        self.select(self.by_group.get(group))
+    }
+
+    pub fn raw_cost(&self, item: &str, count: u64) -> RawCost {
+        let mut cost = RawCost::default();
+        let mut stack = Vec::new();
+        self.expand(item, count as f64, &mut stack, &mut cost);
+        cost
+    }
+
+    fn expand(&self, item: &str, amount: f64, stack: &mut Vec<String>, cost: &mut RawCost) {
+        let recipes = self.recipes_for(item);
+        let loops_back = |input: &ItemCount| input.item == item || stack.contains(&input.item);
+
+        // Only follow recipes that do not loop back into the current chain
+        let recipe = recipes
+            .iter()
+            .find(|recipe| recipe.output.count > 0 && !recipe.inputs.iter().any(loops_back));
+
+        let recipe = match recipe {
+            Some(recipe) => recipe,
+            None => {
+                for recipe in &recipes {
+                    for input in recipe.inputs.iter().filter(|input| loops_back(input)) {
+                        let start = stack
+                            .iter()
+                            .position(|s| *s == input.item)
+                            .unwrap_or(stack.len());
+                        let mut cycle = stack[start..].to_vec();
+                        cycle.push(item.to_string());
+                        cycle.push(input.item.clone());
+                        if !cost.cycles.contains(&cycle) {
+                            cost.cycles.push(cycle);
+                        }
+                    }
+                }
+                *cost.materials.entry(item.to_string()).or_default() += amount;
+                return;
+            }
+        };
+
+        let batches = amount / recipe.output.count as f64;
+        stack.push(item.to_string());
+        for input in &recipe.inputs {
+            self.expand(&input.item, input.count as f64 * batches, stack, cost);
+        }
+        for currency in &recipe.currency_inputs {
+            *cost.currencies.entry(currency.item.clone()).or_default() +=
+                currency.count as f64 * batches;
+        }
+        stack.pop();
+    }
+
+    // Recipe inputs and outputs that are not defined by any item asset
+    pub fn missing_items(&self) -> Vec<MissingItem> {
+        let mut missing = Vec::new();
+        for recipe in &self.recipes {
+            for input in &recipe.inputs {
+                if !self.items.contains(&input.item) {
+                    missing.push(MissingItem {
+                        recipe: recipe.path.clone(),
+                        item: input.item.clone(),
+                        role: "input",
+                    });
+                }
+            }
+            if !self.items.contains(&recipe.output.item) {
+                missing.push(MissingItem {
+                    recipe: recipe.path.clone(),
+                    item: recipe.output.item.clone(),
+                    role: "output",
+                });
+            }
+        }
+        missing
+    }
+}
+
+fn recipes_into_lua(lua: &mlua::Lua, recipes: Vec<&Recipe>) -> mlua::Result<mlua::Table> {
+    let table = lua.create_table()?;
+    for recipe in recipes {
+        table.push(SBType::from(recipe))?;
+    }
+    Ok(table)
+}
+
+impl mlua::UserData for RecipeGraph {
+    fn add_fields<F: mlua::UserDataFields<Self>>(_: &mut F) {}
+
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("recipes", |lua, this, _: ()| {
+            recipes_into_lua(lua, this.recipes.iter().collect())
+        });
+
+        methods.add_method("craftable_from", |lua, this, item: String| {
+            recipes_into_lua(lua, this.craftable_from(&item))
+        });
+
+        methods.add_method("recipes_for", |lua, this, item: String| {
+            recipes_into_lua(lua, this.recipes_for(&item))
+        });
+
+        methods.add_method("group", |lua, this, group: String| {
+            recipes_into_lua(lua, this.group(&group))
+        });
+
+        methods.add_method(
+            "raw_cost",
+            |lua, this, (item, count): (String, Option<u64>)| {
+                let cost = this.raw_cost(&item, count.unwrap_or(1));
+                let table = lua.create_table()?;
+                table.set("materials", lua.create_table_from(cost.materials)?)?;
+                table.set("currencies", lua.create_table_from(cost.currencies)?)?;
+                table.set("cycles", cost.cycles)?;
+                Ok(table)
+            },
+        );
+
+        methods.add_method("missing_items", |lua, this, _: ()| {
+            let table = lua.create_table()?;
+            for missing in this.missing_items() {
+                let entry = lua.create_table()?;
+                entry.set("recipe", missing.recipe)?;
+                entry.set("item", missing.item)?;
+                entry.set("role", missing.role)?;
+                table.push(entry)?;
+            }
+            Ok(table)
+        });
+
+        methods.add_method("errors", |lua, this, _: ()| {
+            let table = lua.create_table()?;
+            for (path, error) in &this.errors {
+                table.set(path.as_str(), error.as_str())?;
+            }
+            Ok(table)
+        });
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use super::AssetReader;
+use super::SBType;
+use super::path;
+
+const SCRIPT_KEYS: [&str; 4] = ["scripts", "script", "primaryScript", "scriptDelta"];
+
+#[derive(Debug, Default)]
+pub struct ScriptGraph {
+    scripts: BTreeSet<String>,
+    // asset or script -> scripts it loads
+    references: BTreeMap<String, BTreeSet<String>>,
+    // script -> assets and scripts loading it
+    referenced_by: BTreeMap<String, BTreeSet<String>>,
+    errors: Vec<(String, String)>,
+}
+
+fn collect_json(value: &SBType, asset: &str, scripts: &mut BTreeSet<String>) {
+    let push = |scripts: &mut BTreeSet<String>, script: &str| {
+        // `scriptDelta` and friends can also hold numbers, only Lua paths are references
+        if script.ends_with(".lua") {
+            scripts.insert(path::resolve(asset, script));
+        }
+    };
+
+    match value {
+        SBType::Object(map) => {
+            for (key, value) in map {
+                match value {
+                    SBType::String(script) if SCRIPT_KEYS.contains(&key.as_str()) => {
+                        push(scripts, script)
+                    }
+                    SBType::Array(array) if SCRIPT_KEYS.contains(&key.as_str()) => {
+                        for script in array.iter().filter_map(SBType::as_str) {
+                            push(scripts, script);
+                        }
+                    }
+                    value => collect_json(value, asset, scripts),
+                }
+            }
+        }
+        SBType::Array(array) => {
+            for value in array {
+                collect_json(value, asset, scripts);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Lua paths anywhere in a patched in value
+fn collect_lua(value: &SBType, asset: &str, scripts: &mut BTreeSet<String>) {
+    match value {
+        SBType::String(script) if script.ends_with(".lua") => {
+            scripts.insert(path::resolve(asset, script));
+        }
+        SBType::Object(map) => {
+            for value in map.values() {
+                collect_lua(value, asset, scripts);
+            }
+        }
+        SBType::Array(array) => {
+            for value in array {
+                collect_lua(value, asset, scripts);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Patches are a list of operations, or a list of such lists. Scripts added or replaced through
+// them count as references of the patch file
+fn collect_patch(value: &SBType, asset: &str, scripts: &mut BTreeSet<String>) {
+    let Some(operations) = value.as_array() else {
+        return;
+    };
+    for operation in operations {
+        if operation.as_array().is_some() {
+            collect_patch(operation, asset, scripts);
+            continue;
+        }
+        let op = operation.get("op").and_then(SBType::as_str);
+        if matches!(op, Some("add" | "replace"))
+            && let Some(value) = operation.get("value")
+        {
+            collect_lua(value, asset, scripts);
+        }
+    }
+}
+
+// Blanks out comments so `require` inside them is ignored, strings are kept
+fn strip_comments(source: &str) -> String {
+    let bytes = source.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                let block = long_bracket(&bytes[i + 2..]);
+                let end = match block {
+                    Some(level) => {
+                        let close = format!("]{}]", "=".repeat(level));
+                        source[i..]
+                            .find(&close)
+                            .map(|end| i + end + close.len())
+                            .unwrap_or(bytes.len())
+                    }
+                    None => source[i..]
+                        .find('\n')
+                        .map(|end| i + end)
+                        .unwrap_or(bytes.len()),
+                };
+                output.push(b' ');
+                i = end;
+            }
+            quote @ (b'"' | b'\'') => {
+                output.push(quote);
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote && bytes[i] != b'\n' {
+                    if bytes[i] == b'\\' && i + 1 < bytes.len() {
+                        output.push(bytes[i]);
+                        i += 1;
+                    }
+                    output.push(bytes[i]);
+                    i += 1;
+                }
+                if i < bytes.len() {
+                    output.push(bytes[i]);
+                    i += 1;
+                }
+            }
+            byte => {
+                output.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&output).to_string()
+}
+
+// Level of a `[[` / `[==[` opening bracket
+fn long_bracket(bytes: &[u8]) -> Option<usize> {
+    if bytes.first() != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[1..].iter().take_while(|&&b| b == b'=').count();
+    (bytes.get(level + 1) == Some(&b'[')).then_some(level)
+}
+
+// `require "/a.lua"`, `require("/a.lua")` and `require '/a.lua'`
+fn collect_requires(source: &str, script: &str, scripts: &mut BTreeSet<String>) {
+    let source = strip_comments(source);
+    let bytes = source.as_bytes();
+    let is_word = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+
+    let mut start = 0;
+    while let Some(found) = source[start..].find("require") {
+        let index = start + found;
+        start = index + "require".len();
+        if index > 0 && (is_word(bytes[index - 1]) || bytes[index - 1] == b'.') {
+            continue;
+        }
+
+        let rest = source[start..].trim_start();
+        let rest = rest.strip_prefix('(').map(str::trim_start).unwrap_or(rest);
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        if let Some(end) = rest[1..].find(quote) {
+            scripts.insert(path::resolve(script, &rest[1..=end]));
+        }
+    }
+}
+
+impl ScriptGraph {
+    pub(super) fn build(reader: &dyn AssetReader) -> Self {
+        let mut graph = Self::default();
+        let mut paths: Vec<String> = reader.paths().into_iter().cloned().collect();
+        paths.sort();
+
+        for asset in paths {
+            let is_script = asset.ends_with(".lua");
+            if is_script {
+                graph.scripts.insert(asset.clone());
+            } else if !path::maybe_json(&asset) {
+                continue;
+            }
+
+            let mut scripts = BTreeSet::new();
+            let file = match reader.file(&asset) {
+                Ok(file) => file,
+                Err(e) => {
+                    graph.errors.push((asset, e.to_string()));
+                    continue;
+                }
+            };
+            if is_script {
+                collect_requires(&String::from_utf8_lossy(&file.bytes), &asset, &mut scripts);
+            } else if let Ok(value) = file.as_json() {
+                if asset.ends_with(".patch") {
+                    collect_patch(&value, &asset, &mut scripts);
+                } else {
+                    collect_json(&value, &asset, &mut scripts);
+                }
+            }
+
+            for script in &scripts {
+                graph
+                    .referenced_by
+                    .entry(script.clone())
+                    .or_default()
+                    .insert(asset.clone());
+            }
+            if !scripts.is_empty() {
+                graph.references.insert(asset, scripts);
+            }
+        }
+
+        graph
+    }
+
+    // Referenced scripts that aren't in the assets, with who references them
+    pub fn missing(&self) -> Vec<(&String, &BTreeSet<String>)> {
+        self.referenced_by
+            .iter()
+            .filter(|(script, _)| !self.scripts.contains(*script))
+            .collect()
+    }
+
+    pub fn unreferenced(&self) -> Vec<String> {
+        self.scripts
+            .iter()
+            .filter(|script| !self.referenced_by.contains_key(*script))
+            .cloned()
+            .collect()
+    }
+
+    fn walk<'a>(
+        &'a self,
+        start: &str,
+        edges: &'a BTreeMap<String, BTreeSet<String>>,
+    ) -> BTreeSet<&'a String> {
+        let mut found = BTreeSet::new();
+        let mut queue = VecDeque::from([start.to_string()]);
+        while let Some(node) = queue.pop_front() {
+            for next in edges.get(&node).into_iter().flatten() {
+                if found.insert(next) {
+                    queue.push_back(next.clone());
+                }
+            }
+        }
+        found
+    }
+
+    // Non-script assets that load `script`, directly or through `require` chains
+    pub fn loaded_by(&self, script: &str) -> Vec<String> {
+        self.walk(script, &self.referenced_by)
+            .into_iter()
+            .filter(|asset| !asset.ends_with(".lua"))
+            .cloned()
+            .collect()
+    }
+
+    // Every script `asset` ends up loading
+    pub fn dependencies(&self, asset: &str) -> Vec<String> {
+        self.walk(asset, &self.references)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+}
+
+impl mlua::UserData for ScriptGraph {
+    fn add_fields<F: mlua::UserDataFields<Self>>(_: &mut F) {}
+
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("missing", |lua, this, _: ()| {
+            let table = lua.create_table()?;
+            for (script, users) in this.missing() {
+                let entry = lua.create_table()?;
+                entry.set("script", script.as_str())?;
+                entry.set("referenced_by", users.iter().cloned().collect::<Vec<_>>())?;
+                table.push(entry)?;
+            }
+            Ok(table)
+        });
+
+        methods.add_method("unreferenced", |_, this, _: ()| Ok(this.unreferenced()));
+
+        methods.add_method("references", |_, this, asset: String| {
+            Ok(this
+                .references
+                .get(&asset)
+                .map(|scripts| scripts.iter().cloned().collect::<Vec<_>>())
+                .unwrap_or_default())
+        });
+
+        methods.add_method("referenced_by", |_, this, script: String| {
+            Ok(this
+                .referenced_by
+                .get(&script)
+                .map(|assets| assets.iter().cloned().collect::<Vec<_>>())
+                .unwrap_or_default())
+        });
+
+        methods.add_method("loaded_by", |_, this, script: String| {
+            Ok(this.loaded_by(&script))
+        });
+
+        methods.add_method("dependencies", |_, this, asset: String| {
+            Ok(this.dependencies(&asset))
+        });
+
+        methods.add_method("errors", |lua, this, _: ()| {
+            let table = lua.create_table()?;
+            for (path, error) in &this.errors {
+                table.set(path.as_str(), error.as_str())?;
+            }
+            Ok(table)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::super::jsonc;
+
+    #[test]
+    fn patches_reference_added_scripts() {
+        let patch = jsonc::parse(
+            r#"[
+                { "op": "add", "path": "/scripts/-", "value": "/scripts/added.lua" },
+                [
+                    { "op": "test", "path": "/script", "value": "/scripts/tested.lua" },
+                    { "op": "replace", "path": "/scripts", "value": ["replaced.lua"] },
+                    { "op": "remove", "path": "/scripts/0" }
+                ],
+                { "op": "add", "path": "/npc", "value": { "scriptConfig": { "s": "/npc.lua" } } }
+            ]"#,
+        )
+        .unwrap();
+        let mut scripts = BTreeSet::new();
+        super::collect_patch(&patch, "/objects/lamp/lamp.object.patch", &mut scripts);
+        assert_eq!(
+            scripts.into_iter().collect::<Vec<_>>(),
+            [
+                "/npc.lua",
+                "/objects/lamp/replaced.lua",
+                "/scripts/added.lua"
+            ]
+        );
+    }
+}
@@ -1,7 +1,7 @@
-use std::collections::HashMap;
 use std::io::Write;
 
 use byteorder::{BigEndian, WriteBytesExt};
+use indexmap::IndexMap;
 
 use super::SBType;
 use super::vlq::{VLQi64, VLQu64};
@@ -43,7 +43,7 @@ pub trait SBWriter: Write {
         Ok(())
     }
 
-    fn write_map(&mut self, map: HashMap<String, SBType>) -> anyhow::Result<()> {
+    fn write_map(&mut self, map: IndexMap<String, SBType>) -> anyhow::Result<()> {
         let length = map.len() as u64;
         self.write_vlq_u64(length)?;
         for (key, value) in map {
@@ -67,3 +67,5 @@ pub trait SBWriter: Write {
         Ok(())
     }
 }
+
+impl SBWriter for Vec<u8> {}
@@ -0,0 +1,658 @@
+use std::fmt;
+
+use indexmap::IndexMap;
+
+use crate::asset::SBType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color(pub [u8; 4]);
+
+impl Color {
+    // rgb, rgba, rrggbb or rrggbbaa
+    pub fn parse(hex: &str) -> Option<Self> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).unwrap_or(0) as u8)
+            .collect();
+        let channels: Vec<u8> = match digits.len() {
+            3 | 4 => digits.iter().map(|d| d * 17).collect(),
+            6 | 8 => digits.chunks(2).map(|d| d[0] * 16 + d[1]).collect(),
+            _ => return None,
+        };
+        Some(Color([
+            channels[0],
+            channels[1],
+            channels[2],
+            channels.get(3).copied().unwrap_or(255),
+        ]))
+    }
+}
+
+// Opaque colors are written without the alpha channel, like the game does
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b, a] = self.0;
+        if a == 255 {
+            write!(f, "{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            write!(f, "{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleMode {
+    Default,
+    Nearest,
+    Bilinear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Multiply,
+    Screen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskMode {
+    Add,
+    Subtract,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Replace(Vec<(Color, Color)>),
+    Scale {
+        mode: ScaleMode,
+        x: f64,
+        y: f64,
+    },
+    // Pixel rectangle, measured from the bottom left of the image
+    Crop {
+        left: i64,
+        bottom: i64,
+        right: i64,
+        top: i64,
+    },
+    Blend {
+        mode: BlendMode,
+        image: String,
+        x: i64,
+        y: i64,
+    },
+    Multiply(Color),
+    Flip {
+        x: bool,
+        y: bool,
+    },
+    HueShift(f64),
+    Saturation(f64),
+    Brightness(f64),
+    Fade {
+        color: Color,
+        amount: f64,
+    },
+    Border {
+        outline: bool,
+        size: u32,
+        start: Color,
+        end: Color,
+    },
+    Mask {
+        mode: MaskMode,
+        image: String,
+        x: i64,
+        y: i64,
+    },
+    SetColor(Color),
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Replace(_) => "replace",
+            Operation::Scale { mode, .. } => match mode {
+                ScaleMode::Default => "scale",
+                ScaleMode::Nearest => "scalenearest",
+                ScaleMode::Bilinear => "scalebilinear",
+            },
+            Operation::Crop { .. } => "crop",
+            Operation::Blend { mode, .. } => match mode {
+                BlendMode::Multiply => "blendmult",
+                BlendMode::Screen => "blendscreen",
+            },
+            Operation::Multiply(_) => "multiply",
+            Operation::Flip { x, y } => match (x, y) {
+                (true, true) => "flipxy",
+                (false, true) => "flipy",
+                _ => "flipx",
+            },
+            Operation::HueShift(_) => "hueshift",
+            Operation::Saturation(_) => "saturation",
+            Operation::Brightness(_) => "brightness",
+            Operation::Fade { .. } => "fade",
+            Operation::Border { outline, .. } => {
+                if *outline {
+                    "outline"
+                } else {
+                    "border"
+                }
+            }
+            Operation::Mask { mode, .. } => match mode {
+                MaskMode::Add => "addmask",
+                MaskMode::Subtract => "submask",
+            },
+            Operation::SetColor(_) => "setcolor",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Arguments of one operation, with the byte offset each one starts at
+struct Arguments<'a> {
+    name: &'a str,
+    position: usize,
+    values: Vec<(usize, &'a str)>,
+}
+
+impl<'a> Arguments<'a> {
+    fn error(&self, position: usize, message: String) -> ParseError {
+        ParseError { position, message }
+    }
+
+    fn count(&self, min: usize, max: usize) -> Result<(), ParseError> {
+        let count = self.values.len();
+        if count < min || count > max {
+            let expected = if min == max {
+                min.to_string()
+            } else {
+                format!("{} to {}", min, max)
+            };
+            return Err(self.error(
+                self.position,
+                format!(
+                    "'{}' takes {} arguments but got {}",
+                    self.name, expected, count
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn text(&self, index: usize) -> Result<&'a str, ParseError> {
+        match self.values.get(index) {
+            Some((_, value)) => Ok(value),
+            None => Err(self.error(
+                self.position,
+                format!("'{}' is missing argument {}", self.name, index + 1),
+            )),
+        }
+    }
+
+    fn position_of(&self, index: usize) -> usize {
+        self.values
+            .get(index)
+            .map(|(position, _)| *position)
+            .unwrap_or(self.position)
+    }
+
+    fn float(&self, index: usize) -> Result<f64, ParseError> {
+        let text = self.text(index)?;
+        text.parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| {
+                self.error(
+                    self.position_of(index),
+                    format!("Invalid number '{}'", text),
+                )
+            })
+    }
+
+    fn int(&self, index: usize) -> Result<i64, ParseError> {
+        let text = self.text(index)?;
+        text.parse::<i64>().map_err(|_| {
+            self.error(
+                self.position_of(index),
+                format!("Invalid integer '{}'", text),
+            )
+        })
+    }
+
+    fn int_or_zero(&self, index: usize) -> Result<i64, ParseError> {
+        if index < self.values.len() {
+            self.int(index)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn color(&self, index: usize) -> Result<Color, ParseError> {
+        let text = self.text(index)?;
+        Color::parse(text)
+            .ok_or_else(|| self.error(self.position_of(index), format!("Invalid color '{}'", text)))
+    }
+}
+
+fn parse_operation(arguments: &Arguments) -> Result<Operation, ParseError> {
+    let operation = match arguments.name {
+        "replace" => {
+            if !arguments.values.len().is_multiple_of(2) {
+                return Err(arguments.error(
+                    arguments.position_of(arguments.values.len() - 1),
+                    "'replace' color without a replacement".to_string(),
+                ));
+            }
+            let mut colors = Vec::with_capacity(arguments.values.len() / 2);
+            for i in (0..arguments.values.len()).step_by(2) {
+                colors.push((arguments.color(i)?, arguments.color(i + 1)?));
+            }
+            Operation::Replace(colors)
+        }
+        "scale" | "scalenearest" | "scalebilinear" => {
+            arguments.count(1, 2)?;
+            let x = arguments.float(0)?;
+            let y = if arguments.values.len() > 1 {
+                arguments.float(1)?
+            } else {
+                x
+            };
+            let mode = match arguments.name {
+                "scalenearest" => ScaleMode::Nearest,
+                "scalebilinear" => ScaleMode::Bilinear,
+                _ => ScaleMode::Default,
+            };
+            Operation::Scale { mode, x, y }
+        }
+        "crop" => {
+            arguments.count(4, 4)?;
+            Operation::Crop {
+                left: arguments.int(0)?,
+                bottom: arguments.int(1)?,
+                right: arguments.int(2)?,
+                top: arguments.int(3)?,
+            }
+        }
+        "blendmult" | "blendscreen" => {
+            arguments.count(1, 3)?;
+            Operation::Blend {
+                mode: if arguments.name == "blendmult" {
+                    BlendMode::Multiply
+                } else {
+                    BlendMode::Screen
+                },
+                image: arguments.text(0)?.to_string(),
+                x: arguments.int_or_zero(1)?,
+                y: arguments.int_or_zero(2)?,
+            }
+        }
+        "addmask" | "submask" => {
+            arguments.count(1, 3)?;
+            Operation::Mask {
+                mode: if arguments.name == "addmask" {
+                    MaskMode::Add
+                } else {
+                    MaskMode::Subtract
+                },
+                image: arguments.text(0)?.to_string(),
+                x: arguments.int_or_zero(1)?,
+                y: arguments.int_or_zero(2)?,
+            }
+        }
+        "multiply" => {
+            arguments.count(1, 1)?;
+            Operation::Multiply(arguments.color(0)?)
+        }
+        "flipx" | "flipy" | "flipxy" => {
+            arguments.count(0, 0)?;
+            Operation::Flip {
+                x: arguments.name != "flipy",
+                y: arguments.name != "flipx",
+            }
+        }
+        "hueshift" => {
+            arguments.count(1, 1)?;
+            Operation::HueShift(arguments.float(0)?)
+        }
+        "saturation" => {
+            arguments.count(1, 1)?;
+            Operation::Saturation(arguments.float(0)?)
+        }
+        "brightness" => {
+            arguments.count(1, 1)?;
+            Operation::Brightness(arguments.float(0)?)
+        }
+        "fade" => {
+            arguments.count(2, 2)?;
+            Operation::Fade {
+                color: arguments.color(0)?,
+                amount: arguments.float(1)?,
+            }
+        }
+        "border" | "outline" => {
+            arguments.count(2, 3)?;
+            let size = arguments.int(0)?;
+            if size < 0 {
+                return Err(arguments.error(
+                    arguments.position_of(0),
+                    format!("Invalid border size '{}'", size),
+                ));
+            }
+            let start = arguments.color(1)?;
+            let end = if arguments.values.len() > 2 {
+                arguments.color(2)?
+            } else {
+                start
+            };
+            Operation::Border {
+                outline: arguments.name == "outline",
+                size: size as u32,
+                start,
+                end,
+            }
+        }
+        "setcolor" => {
+            arguments.count(1, 1)?;
+            Operation::SetColor(arguments.color(0)?)
+        }
+        name => {
+            return Err(
+                arguments.error(arguments.position, format!("Unknown directive '{}'", name))
+            );
+        }
+    };
+    Ok(operation)
+}
+
+// Parses `?op=arg;arg?op;arg=arg...`, both `=` and `;` separate arguments
+pub fn parse(source: &str) -> Result<Vec<Operation>, ParseError> {
+    let mut operations = Vec::new();
+    if source.is_empty() {
+        return Ok(operations);
+    }
+    if !source.starts_with('?') {
+        return Err(ParseError {
+            position: 0,
+            message: "Directives must start with '?'".to_string(),
+        });
+    }
+
+    let mut offset = 0;
+    for segment in source.split('?') {
+        let position = offset;
+        offset += segment.len() + 1;
+        if segment.is_empty() {
+            continue;
+        }
+
+        let mut parts = Vec::new();
+        let mut start = 0;
+        for (i, c) in segment.char_indices() {
+            if c == '=' || c == ';' {
+                parts.push((position + start, &segment[start..i]));
+                start = i + 1;
+            }
+        }
+        parts.push((position + start, &segment[start..]));
+
+        let (name_position, name) = parts.remove(0);
+        // A trailing separator leaves an empty argument, which the game ignores
+        if parts.last().is_some_and(|(_, value)| value.is_empty()) {
+            parts.pop();
+        }
+        let arguments = Arguments {
+            name,
+            position: name_position,
+            values: parts,
+        };
+        operations.push(parse_operation(&arguments)?);
+    }
+
+    Ok(operations)
+}
+
+fn offset(image: &str, x: i64, y: i64) -> String {
+    if x == 0 && y == 0 {
+        image.to_string()
+    } else {
+        format!("{};{};{}", image, x, y)
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name();
+        match self {
+            Operation::Replace(colors) => {
+                write!(f, "{}", name)?;
+                for (from, to) in colors {
+                    write!(f, ";{}={}", from, to)?;
+                }
+                Ok(())
+            }
+            Operation::Scale { x, y, .. } => {
+                if x == y {
+                    write!(f, "{}={}", name, x)
+                } else {
+                    write!(f, "{}={};{}", name, x, y)
+                }
+            }
+            Operation::Crop {
+                left,
+                bottom,
+                right,
+                top,
+            } => write!(f, "{};{};{};{};{}", name, left, bottom, right, top),
+            Operation::Blend { image, x, y, .. } | Operation::Mask { image, x, y, .. } => {
+                write!(f, "{}={}", name, offset(image, *x, *y))
+            }
+            Operation::Multiply(color) | Operation::SetColor(color) => {
+                write!(f, "{}={}", name, color)
+            }
+            Operation::Flip { .. } => write!(f, "{}", name),
+            Operation::HueShift(amount)
+            | Operation::Saturation(amount)
+            | Operation::Brightness(amount) => write!(f, "{}={}", name, amount),
+            Operation::Fade { color, amount } => write!(f, "{}={}={}", name, color, amount),
+            Operation::Border {
+                size, start, end, ..
+            } => {
+                if start == end {
+                    write!(f, "{}={};{}", name, size, start)
+                } else {
+                    write!(f, "{}={};{};{}", name, size, start, end)
+                }
+            }
+        }
+    }
+}
+
+pub fn stringify(operations: &[Operation]) -> String {
+    operations
+        .iter()
+        .map(|operation| format!("?{}", operation))
+        .collect()
+}
+
+fn color_value(color: &Color) -> SBType {
+    SBType::String(color.to_string())
+}
+
+impl From<&Operation> for SBType {
+    fn from(value: &Operation) -> Self {
+        let mut map = IndexMap::new();
+        let mut set = |key: &str, value: SBType| {
+            map.insert(key.to_string(), value);
+        };
+
+        set("op", SBType::String(value.name().to_string()));
+
+        match value {
+            Operation::Replace(colors) => {
+                let colors = colors
+                    .iter()
+                    .map(|(from, to)| {
+                        let mut pair = IndexMap::new();
+                        pair.insert("from".to_string(), color_value(from));
+                        pair.insert("to".to_string(), color_value(to));
+                        SBType::Object(pair)
+                    })
+                    .collect();
+                set("colors", SBType::Array(colors));
+            }
+            Operation::Scale { x, y, .. } => {
+                set("x", SBType::Float(*x));
+                set("y", SBType::Float(*y));
+            }
+            Operation::Crop {
+                left,
+                bottom,
+                right,
+                top,
+            } => {
+                set("left", SBType::Int(*left));
+                set("bottom", SBType::Int(*bottom));
+                set("right", SBType::Int(*right));
+                set("top", SBType::Int(*top));
+            }
+            Operation::Blend { image, x, y, .. } | Operation::Mask { image, x, y, .. } => {
+                set("image", SBType::String(image.clone()));
+                set("x", SBType::Int(*x));
+                set("y", SBType::Int(*y));
+            }
+            Operation::Multiply(color) | Operation::SetColor(color) => {
+                set("color", color_value(color))
+            }
+            Operation::Flip { .. } => {}
+            Operation::HueShift(amount)
+            | Operation::Saturation(amount)
+            | Operation::Brightness(amount) => set("amount", SBType::Float(*amount)),
+            Operation::Fade { color, amount } => {
+                set("color", color_value(color));
+                set("amount", SBType::Float(*amount));
+            }
+            Operation::Border {
+                size, start, end, ..
+            } => {
+                set("size", SBType::Int(*size as i64));
+                set("start", color_value(start));
+                set("end", color_value(end));
+            }
+        }
+
+        SBType::Object(map)
+    }
+}
+
+// Builds the operation back from its table form by serializing the fields and reparsing,
+// so tables get the same validation as strings
+impl TryFrom<&SBType> for Operation {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &SBType) -> Result<Self, Self::Error> {
+        let Some(name) = value.get("op").and_then(SBType::as_str) else {
+            anyhow::bail!("Directive table is missing 'op'");
+        };
+        let field = |key: &str| -> anyhow::Result<String> {
+            match value.get(key) {
+                Some(SBType::String(text)) => Ok(text.clone()),
+                Some(SBType::Int(number)) => Ok(number.to_string()),
+                Some(SBType::Float(number)) => Ok(number.to_string()),
+                _ => anyhow::bail!("Directive '{}' is missing '{}'", name, key),
+            }
+        };
+        let field_or = |key: &str, default: &str| -> anyhow::Result<String> {
+            match value.get(key) {
+                Some(_) => field(key),
+                None => Ok(default.to_string()),
+            }
+        };
+
+        let source = match name {
+            "replace" => {
+                let mut source = "?replace".to_string();
+                let colors = value.get("colors").and_then(SBType::as_array);
+                for pair in colors.into_iter().flatten() {
+                    let color = |key: &str| pair.get(key).and_then(SBType::as_str);
+                    match (color("from"), color("to")) {
+                        (Some(from), Some(to)) => source.push_str(&format!(";{}={}", from, to)),
+                        _ => anyhow::bail!("Replace colors need 'from' and 'to'"),
+                    }
+                }
+                source
+            }
+            "scale" | "scalenearest" | "scalebilinear" => {
+                let x = field("x")?;
+                format!("?{}={};{}", name, x, field_or("y", &x)?)
+            }
+            "crop" => format!(
+                "?crop;{};{};{};{}",
+                field("left")?,
+                field("bottom")?,
+                field("right")?,
+                field("top")?
+            ),
+            "blendmult" | "blendscreen" | "addmask" | "submask" => format!(
+                "?{}={};{};{}",
+                name,
+                field("image")?,
+                field_or("x", "0")?,
+                field_or("y", "0")?
+            ),
+            "multiply" | "setcolor" => format!("?{}={}", name, field("color")?),
+            "flipx" | "flipy" | "flipxy" => format!("?{}", name),
+            "hueshift" | "saturation" | "brightness" => format!("?{}={}", name, field("amount")?),
+            "fade" => format!("?fade={}={}", field("color")?, field("amount")?),
+            "border" | "outline" => {
+                let start = field("start")?;
+                format!(
+                    "?{}={};{};{}",
+                    name,
+                    field("size")?,
+                    start,
+                    field_or("end", &start)?
+                )
+            }
+            name => anyhow::bail!("Unknown directive '{}'", name),
+        };
+
+        match parse(&source)?.pop() {
+            Some(operation) => Ok(operation),
+            None => anyhow::bail!("Empty directive"),
+        }
+    }
+}
+
+pub fn lua_parse(_: &mlua::Lua, source: String) -> mlua::Result<SBType> {
+    let operations = parse(&source).map_err(mlua::Error::external)?;
+    Ok(SBType::Array(operations.iter().map(SBType::from).collect()))
+}
+
+pub fn lua_stringify(_: &mlua::Lua, value: SBType) -> mlua::Result<String> {
+    let operations = match &value {
+        SBType::Array(operations) => operations
+            .iter()
+            .map(Operation::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        // A single operation table
+        SBType::Object(_) => vec![Operation::try_from(&value)?],
+        _ => return Err(mlua::Error::external("Expected a directive table")),
+    };
+    Ok(stringify(&operations))
+}
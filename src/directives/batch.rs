@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use indexmap::IndexMap;
+
+use super::item::{self, ItemOptions};
+use super::outfit::{self, OutfitTemplate};
+use crate::asset::SBType;
+use crate::asset::jsonc;
+use crate::utils::bitmap::Bitmap;
+use crate::utils::image::{Image, PixelFormat};
+
+// Item kind -> bundled template and the file suffixes of its images, in template layer order
+const KINDS: [(&str, &str, &[&str]); 4] = [
+    ("chest", "chest", &["chest", "fsleeve", "bsleeve"]),
+    ("legs", "pants", &["legs"]),
+    ("back", "back", &["back"]),
+    ("head", "hat", &["head"]),
+];
+
+#[derive(Default)]
+pub struct BatchOptions {
+    pub rarity: Option<String>,
+    pub hide_body: bool,
+}
+
+pub struct Batch {
+    pub items: Vec<SBType>,
+    // "<set>.<kind>" -> error
+    pub errors: IndexMap<String, String>,
+}
+
+// `<name>.<suffix>.png` files grouped by name
+fn scan(directory: &Path) -> anyhow::Result<BTreeMap<String, BTreeMap<String, PathBuf>>> {
+    let suffixes: Vec<&str> = KINDS
+        .iter()
+        .flat_map(|(_, _, suffixes)| suffixes.iter().copied())
+        .collect();
+
+    let mut sets: BTreeMap<String, BTreeMap<String, PathBuf>> = BTreeMap::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some(stem) = file_name.strip_suffix(".png") else {
+            continue;
+        };
+        let Some((name, suffix)) = stem.rsplit_once('.') else {
+            continue;
+        };
+        if !name.is_empty() && suffixes.contains(&suffix) {
+            sets.entry(name.to_string())
+                .or_default()
+                .insert(suffix.to_string(), path.clone());
+        }
+    }
+    Ok(sets)
+}
+
+// Generators read images bottom row first
+fn bottom_up(bitmap: &Bitmap) -> Vec<u8> {
+    let row = bitmap.width as usize * 4;
+    bitmap.data.chunks(row).rev().flatten().copied().collect()
+}
+
+fn build_set(
+    name: &str,
+    kind: &str,
+    template: &str,
+    files: &[PathBuf],
+    options: &BatchOptions,
+) -> anyhow::Result<SBType> {
+    let bitmaps = files
+        .iter()
+        .map(|path| {
+            std::fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Bitmap::from_png(&bytes))
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    // The pixel buffers have to outlive the images pointing into them
+    let mut buffers: Vec<Vec<u8>> = bitmaps.iter().map(bottom_up).collect();
+    let images: Vec<Image> = buffers
+        .iter_mut()
+        .zip(&bitmaps)
+        .map(|(buffer, bitmap)| {
+            Image::new(
+                buffer.as_mut_ptr(),
+                bitmap.width,
+                bitmap.height,
+                PixelFormat::RGBA32,
+            )
+        })
+        .collect();
+
+    let template = OutfitTemplate::bundled(template)?;
+    let variant = (kind == "legs" && options.hide_body).then(|| "hideBody".to_string());
+    let item = item::build(
+        &template,
+        &images,
+        ItemOptions {
+            name: name.to_string(),
+            description: None,
+            rarity: options.rarity.clone(),
+            inventory_icon: None,
+            item_name: None,
+            variant,
+            color_options: None,
+        },
+    )?;
+
+    let mut entry = IndexMap::new();
+    entry.insert("name".to_string(), SBType::String(name.to_string()));
+    entry.insert("kind".to_string(), SBType::String(kind.to_string()));
+    entry.insert(
+        "itemName".to_string(),
+        SBType::String(item.item_name.clone()),
+    );
+    entry.insert("count".to_string(), SBType::Int(1));
+    entry.insert("command".to_string(), SBType::String(item.command()));
+    entry.insert(
+        "warnings".to_string(),
+        outfit::warnings_value(&item.warnings),
+    );
+    entry.insert("parameters".to_string(), item.parameters);
+    Ok(SBType::Object(entry))
+}
+
+pub fn run(directory: &Path, options: &BatchOptions) -> anyhow::Result<Batch> {
+    let mut batch = Batch {
+        items: Vec::new(),
+        errors: IndexMap::new(),
+    };
+
+    for (name, files) in scan(directory)? {
+        for (kind, template, suffixes) in KINDS {
+            if !suffixes.iter().any(|suffix| files.contains_key(*suffix)) {
+                continue;
+            }
+            let key = format!("{}.{}", name, kind);
+            let missing: Vec<String> = suffixes
+                .iter()
+                .filter(|suffix| !files.contains_key(**suffix))
+                .map(|suffix| format!("{}.{}.png", name, suffix))
+                .collect();
+            if !missing.is_empty() {
+                batch
+                    .errors
+                    .insert(key, format!("Missing {}", missing.join(", ")));
+                continue;
+            }
+
+            let paths: Vec<PathBuf> = suffixes
+                .iter()
+                .map(|suffix| files[*suffix].clone())
+                .collect();
+            match build_set(&name, kind, template, &paths, options) {
+                Ok(item) => batch.items.push(item),
+                Err(e) => {
+                    batch.errors.insert(key, e.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(batch)
+}
+
+impl From<&Batch> for SBType {
+    fn from(value: &Batch) -> Self {
+        let mut map = IndexMap::new();
+        map.insert("items".to_string(), SBType::Array(value.items.clone()));
+        map.insert(
+            "errors".to_string(),
+            SBType::Object(
+                value
+                    .errors
+                    .iter()
+                    .map(|(set, error)| (set.clone(), SBType::String(error.clone())))
+                    .collect(),
+            ),
+        );
+        SBType::Object(map)
+    }
+}
+
+// Writes the manifest to `manifest`, `<dir>/outfits.json` by default
+pub fn lua_batch(
+    _: &mlua::Lua,
+    (directory, options): (String, Option<mlua::Table>),
+) -> mlua::Result<SBType> {
+    let mut batch_options = BatchOptions::default();
+    let mut manifest = Path::new(&directory).join("outfits.json");
+    if let Some(options) = options {
+        batch_options.rarity = options.get("rarity")?;
+        batch_options.hide_body = options.get::<Option<bool>>("hideBody")?.unwrap_or(false);
+        if let Some(path) = options.get::<Option<String>>("manifest")? {
+            manifest = PathBuf::from(path);
+        }
+    }
+
+    let batch = run(Path::new(&directory), &batch_options).map_err(mlua::Error::external)?;
+    let result = SBType::from(&batch);
+    std::fs::write(&manifest, jsonc::stringify(&result, Some(2)))?;
+    Ok(result)
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use indexmap::IndexMap;
+
+use super::ast::{Color, Operation, parse};
+use super::outfit::{self, Encoding, OutfitTemplate};
+use crate::asset::SBType;
+use crate::utils::bitmap::Bitmap;
+use crate::utils::template;
+
+pub struct Decoded {
+    pub template: String,
+    // Variant whose base directives matched, `None` for the main ones or no match
+    pub variant: Option<String>,
+    // Operations taken up by the matched base directives, 0 without a match
+    pub base: usize,
+    // Sprite sheet per template layer
+    pub layers: Vec<(String, Bitmap)>,
+    pub pixels: usize,
+    // Replacements that don't map to a template position
+    pub unknown: usize,
+}
+
+// The outfit colors live in the last run of replace operations, everything before it builds
+// the template layout
+fn final_replace(operations: &[Operation]) -> Vec<(Color, Color)> {
+    let Some(end) = operations
+        .iter()
+        .rposition(|operation| matches!(operation, Operation::Replace(_)))
+    else {
+        return Vec::new();
+    };
+    let start = operations[..end]
+        .iter()
+        .rposition(|operation| !matches!(operation, Operation::Replace(_)))
+        .map(|index| index + 1)
+        .unwrap_or(0);
+
+    operations[start..=end]
+        .iter()
+        .flat_map(|operation| match operation {
+            Operation::Replace(colors) => colors.clone(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+fn sheet_size(template: &OutfitTemplate, frames: &[Vec<String>]) -> (u32, u32) {
+    (
+        frames.first().map(Vec::len).unwrap_or(0) as u32 * template.frame_width,
+        frames.len() as u32 * template.frame_height,
+    )
+}
+
+// Longest base chain of the template or one of its variants that starts the directives
+fn matching_base(
+    template: &OutfitTemplate,
+    operations: &[Operation],
+) -> anyhow::Result<Option<(Option<String>, usize)>> {
+    let variants = std::iter::once(None).chain(template.variants.keys().map(Some));
+    let mut best: Option<(Option<String>, usize)> = None;
+    for variant in variants {
+        let base = parse(&template.base_directives(variant.map(String::as_str))?)?;
+        if !base.is_empty()
+            && operations.starts_with(&base)
+            && best.as_ref().is_none_or(|(_, length)| base.len() > *length)
+        {
+            best = Some((variant.cloned(), base.len()));
+        }
+    }
+    Ok(best)
+}
+
+fn decode_with(
+    template: &OutfitTemplate,
+    name: &str,
+    operations: &[Operation],
+) -> anyhow::Result<Decoded> {
+    let (variant, base) = matching_base(template, operations)?.unwrap_or((None, 0));
+    let colors = final_replace(&operations[base..]);
+    let mut layers: Vec<(String, Bitmap)> = template
+        .layers
+        .iter()
+        .map(|layer| {
+            let (width, height) = sheet_size(template, &layer.frames);
+            (layer.name.clone(), Bitmap::new(width, height))
+        })
+        .collect();
+
+    // Frame id -> (layer, column, row) of every frame using it
+    let mut frames: HashMap<u8, Vec<(usize, u32, u32)>> = HashMap::new();
+    for (index, layer) in template.layers.iter().enumerate() {
+        for (row, ids) in layer.frames.iter().enumerate() {
+            for (column, id) in ids.iter().enumerate() {
+                if let Ok(id) = u8::from_str_radix(id, 16) {
+                    frames
+                        .entry(id)
+                        .or_default()
+                        .push((index, column as u32, row as u32));
+                }
+            }
+        }
+    }
+
+    // First cell of a block -> frame id, for cell templates
+    let blocks: HashMap<(u32, u32), u8> = match template.encoding {
+        Encoding::Cells => template
+            .cell_blocks()
+            .map(|blocks| blocks.into_iter().map(|(id, cell)| (cell, id)).collect())
+            .unwrap_or_default(),
+        _ => HashMap::new(),
+    };
+    let cell_size = |size: u32| size.div_ceil(template::CELL_SIZE) * template::CELL_SIZE;
+
+    let mut pixels = 0;
+    let mut unknown = 0;
+    for (from, to) in &colors {
+        // Transparent sprite pixels are never part of the mapping, except for sheet clearing
+        if to.0[3] == 0 {
+            continue;
+        }
+        let mut placed = false;
+        match template.encoding {
+            Encoding::Frame => {
+                let [x, id, y, alpha] = from.0.map(|c| c as u32);
+                let targets = frames.get(&(id as u8)).filter(|_| {
+                    alpha == 0 && x < template.frame_width && y < template.frame_height
+                });
+                for (layer, column, row) in targets.into_iter().flatten() {
+                    // Template y counts up from the bottom of the frame
+                    let px = column * template.frame_width + x;
+                    let py = row * template.frame_height + (template.frame_height - 1 - y);
+                    layers[*layer].1.set_pixel(px, py, to.0);
+                    placed = true;
+                }
+            }
+            Encoding::Cells => {
+                let position = template::sheet_position(from.0).and_then(|(x, y)| {
+                    let (width, height) = (
+                        cell_size(template.frame_width),
+                        cell_size(template.frame_height),
+                    );
+                    let block = (
+                        x / width * width / template::CELL_SIZE,
+                        y / height * height / template::CELL_SIZE,
+                    );
+                    let (x, y) = (x % width, y % height);
+                    (x < template.frame_width && y < template.frame_height).then_some((
+                        blocks.get(&block)?,
+                        x,
+                        y,
+                    ))
+                });
+                let targets = position.and_then(|(id, x, y)| Some((frames.get(id)?, x, y)));
+                if let Some((targets, x, y)) = targets {
+                    for (layer, column, row) in targets {
+                        let px = column * template.frame_width + x;
+                        let py = row * template.frame_height + (template.frame_height - 1 - y);
+                        layers[*layer].1.set_pixel(px, py, to.0);
+                        placed = true;
+                    }
+                }
+            }
+            Encoding::Sheet => {
+                let bitmap = &mut layers[0].1;
+                if let Some((x, y)) = template::sheet_position(from.0)
+                    && x < bitmap.width
+                    && y < bitmap.height
+                {
+                    let height = bitmap.height;
+                    bitmap.set_pixel(x, height - 1 - y, to.0);
+                    placed = true;
+                }
+            }
+        }
+        if placed {
+            pixels += 1;
+        } else {
+            unknown += 1;
+        }
+    }
+
+    Ok(Decoded {
+        template: name.to_string(),
+        variant,
+        base,
+        layers,
+        pixels,
+        unknown,
+    })
+}
+
+// Without a template every bundled one is tried. The one whose base directives start the
+// directives wins, the longest base if several do, and the most placed pixels otherwise
+pub fn decode(directives: &str, template: Option<&SBType>) -> anyhow::Result<Decoded> {
+    let operations = parse(directives)?;
+    if final_replace(&operations).is_empty() {
+        anyhow::bail!("Directives have no color replacements to decode");
+    }
+
+    if let Some(template) = template {
+        let name = template.as_str().unwrap_or("custom");
+        let template = outfit::template_value(template)?;
+        return decode_with(&template, name, &operations);
+    }
+
+    let mut best: Option<Decoded> = None;
+    for name in outfit::bundled_names() {
+        let decoded = decode_with(&OutfitTemplate::bundled(name)?, name, &operations)?;
+        if best
+            .as_ref()
+            .is_none_or(|best| (decoded.base, decoded.pixels) > (best.base, best.pixels))
+        {
+            best = Some(decoded);
+        }
+    }
+    match best {
+        Some(best) if best.pixels > 0 => Ok(best),
+        _ => anyhow::bail!("Directives don't match any bundled template"),
+    }
+}
+
+// `output` is a PNG path for single layer templates, a directory for the others, or a table of
+// layer name -> path
+fn output_paths(output: &SBType, layers: &[(String, Bitmap)]) -> anyhow::Result<Vec<String>> {
+    match output {
+        SBType::String(path) if layers.len() == 1 => Ok(vec![path.clone()]),
+        SBType::String(directory) => Ok(layers
+            .iter()
+            .map(|(name, _)| {
+                Path::new(directory)
+                    .join(format!("{}.png", name))
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()),
+        SBType::Object(paths) => layers
+            .iter()
+            .map(|(name, _)| {
+                paths
+                    .get(name)
+                    .and_then(SBType::as_str)
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow::anyhow!("No output path for layer '{}'", name))
+            })
+            .collect(),
+        _ => anyhow::bail!("Output must be a path or a table of layer paths"),
+    }
+}
+
+pub fn lua_decode(
+    _: &mlua::Lua,
+    (directives, output, template): (String, SBType, Option<SBType>),
+) -> mlua::Result<SBType> {
+    let decoded = decode(&directives, template.as_ref()).map_err(mlua::Error::external)?;
+    let paths = output_paths(&output, &decoded.layers).map_err(mlua::Error::external)?;
+
+    let mut files = IndexMap::new();
+    for ((name, bitmap), path) in decoded.layers.iter().zip(paths) {
+        if let Some(parent) = Path::new(&path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, bitmap.to_png().map_err(mlua::Error::external)?)?;
+        files.insert(name.clone(), SBType::String(path));
+    }
+
+    let mut result = IndexMap::new();
+    result.insert("template".to_string(), SBType::String(decoded.template));
+    if let Some(variant) = decoded.variant {
+        result.insert("variant".to_string(), SBType::String(variant));
+    }
+    result.insert("files".to_string(), SBType::Object(files));
+    result.insert("pixels".to_string(), SBType::Int(decoded.pixels as i64));
+    result.insert("unknown".to_string(), SBType::Int(decoded.unknown as i64));
+    Ok(SBType::Object(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::*;
+    use super::super::outfit::Generated;
+    use super::*;
+    use crate::utils::image::Image;
+
+    fn generate(template: &OutfitTemplate, variant: Option<&str>) -> (Vec<Bitmap>, Generated) {
+        let sheets: Vec<Bitmap> = template
+            .layers
+            .iter()
+            .map(|layer| sprite_sheet(template, layer))
+            .collect();
+        let mut buffers: Vec<Vec<u8>> = sheets.iter().map(bottom_up).collect();
+        let images: Vec<Image> = buffers
+            .iter_mut()
+            .zip(&sheets)
+            .map(|(buffer, sheet)| image(buffer, sheet))
+            .collect();
+        let generated = template.generate(&images, variant).unwrap();
+        (sheets, generated)
+    }
+
+    fn check_layers(decoded: &Decoded, sheets: &[Bitmap], label: &str) {
+        assert_eq!(decoded.unknown, 0, "{}", label);
+        for ((layer, bitmap), sheet) in decoded.layers.iter().zip(sheets) {
+            assert_eq!(differences(bitmap, sheet), 0, "{} layer '{}'", label, layer);
+        }
+    }
+
+    #[test]
+    fn bundled_templates_decode_back_to_the_sprites() {
+        for name in outfit::bundled_names() {
+            let template = OutfitTemplate::bundled(name).unwrap();
+            let variants = std::iter::once(None).chain(template.variants.keys().map(Some));
+            for variant in variants {
+                let (sheets, generated) = generate(&template, variant.map(String::as_str));
+                let decoded = decode(&generated.directives, None).unwrap();
+                let label = format!("{} {:?}", name, variant);
+                assert_eq!(decoded.template, name, "{}", label);
+                assert_eq!(decoded.variant.as_ref(), variant, "{}", label);
+                check_layers(&decoded, &sheets, &label);
+            }
+        }
+    }
+
+    #[test]
+    fn sheet_and_cell_templates_decode_back_to_the_sprites() {
+        let mut cells = OutfitTemplate::bundled("chest").unwrap();
+        cells.encoding = Encoding::Cells;
+        (cells.frame_width, cells.frame_height) = (130, 20);
+        let sheet = OutfitTemplate::try_from(
+            &crate::asset::jsonc::parse(
+                r#"{
+                    "frameSize": [150, 100],
+                    "encoding": "sheet",
+                    "layers": [{ "name": "sprite", "frames": [["1", "2"], ["3", ""]] }]
+                }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        for (name, template) in [("cells", cells), ("sheet", sheet)] {
+            let (sheets, generated) = generate(&template, None);
+            let operations = parse(&generated.directives).unwrap();
+            let decoded = decode_with(&template, name, &operations).unwrap();
+            assert!(decoded.base > 0, "{}", name);
+            check_layers(&decoded, &sheets, name);
+        }
+    }
+
+    #[test]
+    fn directives_without_a_base_fall_back_to_pixel_counts() {
+        let template = OutfitTemplate::bundled("pants").unwrap();
+        let (_, generated) = generate(&template, None);
+        let colors = &generated.directives[template.directives.len()..];
+        let decoded = decode(colors, None).unwrap();
+        assert_eq!(decoded.base, 0);
+        assert!(decoded.pixels > 0);
+    }
+}
@@ -0,0 +1,157 @@
+// Test helpers shared by the directive tests
+use std::collections::HashMap;
+
+use super::ast::{Color, Operation, parse};
+use super::outfit::{Layer, OutfitTemplate};
+use crate::utils::bitmap::Bitmap;
+use crate::utils::image::{Image, PixelFormat};
+
+// Stand-ins for the vanilla images the base directives blend with, holding only the pixels
+// they read, in bottom-left coordinates
+pub(super) fn stub_asset(path: &str) -> anyhow::Result<Bitmap> {
+    let (width, height, pixels) = match path {
+        // Bottom to top: no blue, then full blue, which the frame layout turns into y
+        "/monsters/boss/apeboss/apeboss.png" => (
+            1264,
+            396,
+            vec![(1263, 394, [255, 255, 0, 255]), (1263, 395, [255; 4])],
+        ),
+        // Left to right: no red, then full red, which becomes x
+        "/dungeons/other/wreck/key.png" => (
+            757,
+            31,
+            vec![
+                (755, 29, [0, 255, 255, 255]),
+                (756, 29, [255; 4]),
+                (755, 30, [0, 255, 255, 255]),
+                (756, 30, [255; 4]),
+            ],
+        ),
+        "/items/active/weapons/protectorate/aegisaltpistol/beamend.png" => (
+            2,
+            2,
+            vec![
+                (0, 0, [0xa3, 0x55, 0xc0, 0xa5]),
+                (1, 0, [0xa3, 0x55, 0xc0, 0x7b]),
+                (0, 1, [0xff, 0xff, 0xff, 0xa5]),
+                (1, 1, [0xff, 0xff, 0xff, 0x7b]),
+            ],
+        ),
+        _ => anyhow::bail!("No stub for '{}'", path),
+    };
+    let mut bitmap = Bitmap::new(width, height);
+    for (x, y, pixel) in pixels {
+        bitmap.set_pixel(x, height - 1 - y, pixel);
+    }
+    Ok(bitmap)
+}
+
+pub(super) fn filled(width: u32, height: u32, pixel: [u8; 4]) -> Bitmap {
+    let mut bitmap = Bitmap::new(width, height);
+    for chunk in bitmap.data.chunks_exact_mut(4) {
+        chunk.copy_from_slice(&pixel);
+    }
+    bitmap
+}
+
+pub(super) fn crop(bitmap: &Bitmap, x: u32, y: u32, width: u32, height: u32) -> Bitmap {
+    let mut output = Bitmap::new(width, height);
+    for dy in 0..height {
+        for dx in 0..width {
+            output.set_pixel(dx, dy, bitmap.pixel(x + dx, y + dy));
+        }
+    }
+    output
+}
+
+// Deterministic opaque colors with some transparent pixels mixed in
+pub(super) fn sprite_color(seed: u32) -> [u8; 4] {
+    let hash = seed
+        .wrapping_mul(0x9e37_79b1)
+        .rotate_left(13)
+        .wrapping_mul(0x85eb_ca77);
+    let [r, g, b, a] = hash.to_le_bytes();
+    if a < 32 { [0; 4] } else { [r, g, b, 255] }
+}
+
+// Frames with the same id share their pixels, the same way they share template colors
+pub(super) fn sprite_sheet(template: &OutfitTemplate, layer: &Layer) -> Bitmap {
+    let (frame_width, frame_height) = (template.frame_width, template.frame_height);
+    let mut sheet = Bitmap::new(
+        layer.frames[0].len() as u32 * frame_width,
+        layer.frames.len() as u32 * frame_height,
+    );
+    for (row, ids) in layer.frames.iter().enumerate() {
+        for (column, id) in ids.iter().enumerate() {
+            let Ok(id) = u32::from_str_radix(id, 16) else {
+                continue;
+            };
+            for y in 0..frame_height {
+                for x in 0..frame_width {
+                    let seed = (id * frame_height + y) * frame_width + x;
+                    sheet.set_pixel(
+                        column as u32 * frame_width + x,
+                        row as u32 * frame_height + y,
+                        sprite_color(seed),
+                    );
+                }
+            }
+        }
+    }
+    sheet
+}
+
+// Generators read images bottom row first
+pub(super) fn bottom_up(bitmap: &Bitmap) -> Vec<u8> {
+    let row = bitmap.width as usize * 4;
+    bitmap.data.chunks(row).rev().flatten().copied().collect()
+}
+
+pub(super) fn image(buffer: &mut [u8], bitmap: &Bitmap) -> Image {
+    Image::new(
+        buffer.as_mut_ptr(),
+        bitmap.width,
+        bitmap.height,
+        PixelFormat::RGBA32,
+    )
+}
+
+// Pixels that differ, fully transparent pixels are equal whatever their color
+pub(super) fn differences(a: &Bitmap, b: &Bitmap) -> usize {
+    assert_eq!((a.width, a.height), (b.width, b.height));
+    let visible = |pixel: &[u8]| {
+        if pixel[3] == 0 {
+            [0; 4]
+        } else {
+            [pixel[0], pixel[1], pixel[2], pixel[3]]
+        }
+    };
+    a.data
+        .chunks_exact(4)
+        .zip(b.data.chunks_exact(4))
+        .filter(|(a, b)| visible(a) != visible(b))
+        .count()
+}
+
+// Frame templates reduce every vanilla frame to one pixel and replace it with the frame id,
+// a frame filled with that pixel's color stands in for the vanilla one
+pub(super) fn keyed_base(name: &str, variant: Option<&str>) -> impl Fn(u8) -> Bitmap {
+    let template = OutfitTemplate::bundled(name).unwrap();
+    let directives = match variant {
+        Some(variant) => template.variants[variant].clone(),
+        None => template.directives.clone(),
+    };
+    let keys: HashMap<u8, Color> = parse(&directives)
+        .unwrap()
+        .into_iter()
+        .find_map(|operation| match operation {
+            Operation::Replace(colors) => Some(colors),
+            _ => None,
+        })
+        .unwrap()
+        .into_iter()
+        .map(|(from, to)| (to.0[1], from))
+        .collect();
+    let (width, height) = (template.frame_width, template.frame_height);
+    move |id| filled(width, height, keys[&id].0)
+}
@@ -0,0 +1,133 @@
+use indexmap::IndexMap;
+
+use super::outfit::{self, LayerWarning, OutfitTemplate};
+use crate::asset::SBType;
+use crate::asset::jsonc;
+use crate::utils::image;
+
+const RARITIES: [&str; 5] = ["Common", "Uncommon", "Rare", "Legendary", "Essential"];
+
+pub struct ItemOptions {
+    pub name: String,
+    pub description: Option<String>,
+    pub rarity: Option<String>,
+    pub inventory_icon: Option<String>,
+    pub item_name: Option<String>,
+    pub variant: Option<String>,
+    pub color_options: Option<SBType>,
+}
+
+pub struct Item {
+    pub item_name: String,
+    pub parameters: SBType,
+    pub warnings: Vec<LayerWarning>,
+}
+
+impl Item {
+    // Parameters go in single quotes, the chat command parser unescapes backslashes inside
+    // them as well, so those and the quotes themselves need escaping
+    pub fn command(&self) -> String {
+        let parameters = jsonc::stringify(&self.parameters, None)
+            .replace('\\', "\\\\")
+            .replace('\'', "\\'");
+        format!("/spawnitem {} 1 '{}'", self.item_name, parameters)
+    }
+}
+
+fn rarity(rarity: &str) -> anyhow::Result<&'static str> {
+    RARITIES
+        .iter()
+        .find(|known| known.eq_ignore_ascii_case(rarity))
+        .copied()
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown rarity '{}', expected one of {}",
+                rarity,
+                RARITIES.join(", ")
+            )
+        })
+}
+
+pub fn build(
+    template: &OutfitTemplate,
+    images: &[image::Image],
+    options: ItemOptions,
+) -> anyhow::Result<Item> {
+    let Some(item_name) = options.item_name.or_else(|| template.item_name.clone()) else {
+        anyhow::bail!("Template has no base item, pass itemName");
+    };
+    let generated = template.generate(images, options.variant.as_deref())?;
+
+    let mut parameters = IndexMap::new();
+    parameters.insert("shortdescription".to_string(), SBType::String(options.name));
+    if let Some(description) = options.description {
+        parameters.insert("description".to_string(), SBType::String(description));
+    }
+    let rarity = rarity(options.rarity.as_deref().unwrap_or("Common"))?;
+    parameters.insert("rarity".to_string(), SBType::String(rarity.to_string()));
+    if let Some(icon) = options
+        .inventory_icon
+        .or_else(|| template.inventory_icon.clone())
+    {
+        parameters.insert("inventoryIcon".to_string(), SBType::String(icon));
+    }
+    if let Some(color_options) = options.color_options {
+        if !matches!(color_options, SBType::Array(_)) {
+            anyhow::bail!("colorOptions must be an array of color replacement tables");
+        }
+        parameters.insert("colorOptions".to_string(), color_options);
+        parameters.insert("colorIndex".to_string(), SBType::Int(0));
+    }
+    parameters.insert(
+        "directives".to_string(),
+        SBType::String(generated.directives),
+    );
+
+    Ok(Item {
+        item_name,
+        parameters: SBType::Object(parameters),
+        warnings: generated.warnings,
+    })
+}
+
+// `kind` names a bundled template, `template` takes a template table instead
+pub fn lua_build_item(_: &mlua::Lua, options: mlua::Table) -> mlua::Result<SBType> {
+    let template = match options.get::<Option<SBType>>("template")? {
+        Some(template) => template,
+        None => match options.get::<Option<String>>("kind")? {
+            Some(kind) => SBType::String(kind),
+            None => return Err(mlua::Error::external("build_item needs a kind or template")),
+        },
+    };
+    let template = outfit::template_value(&template).map_err(mlua::Error::external)?;
+    let images: Vec<image::Image> = options
+        .get::<Vec<mlua::AnyUserData>>("images")?
+        .into_iter()
+        .map(image::read_image)
+        .collect::<mlua::Result<_>>()?;
+
+    let item_options = ItemOptions {
+        name: options.get("name")?,
+        description: options.get("description")?,
+        rarity: options.get("rarity")?,
+        inventory_icon: options.get("inventoryIcon")?,
+        item_name: options.get("itemName")?,
+        variant: options.get("variant")?,
+        color_options: options.get("colorOptions")?,
+    };
+    let item = build(&template, &images, item_options).map_err(mlua::Error::external)?;
+
+    let mut result = IndexMap::new();
+    result.insert(
+        "itemName".to_string(),
+        SBType::String(item.item_name.clone()),
+    );
+    result.insert("count".to_string(), SBType::Int(1));
+    result.insert("command".to_string(), SBType::String(item.command()));
+    result.insert(
+        "warnings".to_string(),
+        outfit::warnings_value(&item.warnings),
+    );
+    result.insert("parameters".to_string(), item.parameters);
+    Ok(SBType::Object(result))
+}
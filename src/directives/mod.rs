@@ -1,8 +1,17 @@
+pub mod ast;
 pub mod back;
+pub mod batch;
 pub mod chest;
+pub mod decode;
+#[cfg(test)]
+mod fixtures;
 pub mod hat;
-pub mod pants;
+pub mod item;
 pub mod normal;
+pub mod optimize;
+pub mod outfit;
+pub mod pants;
+pub mod render;
 
 pub fn register_function(lua: &mlua::Lua) -> mlua::Result<mlua::Table> {
     let outfit = lua.create_table()?;
@@ -19,5 +28,41 @@ pub fn register_function(lua: &mlua::Lua) -> mlua::Result<mlua::Table> {
     let generate_hat = lua.create_function(hat::lua_generate)?;
     outfit.set("generate_hat", generate_hat)?;
 
+    let generate_image = lua.create_function(normal::lua_generate)?;
+    outfit.set("generate_image", generate_image)?;
+
+    let generate_outfit = lua.create_function(outfit::lua_generate)?;
+    outfit.set("generate_outfit", generate_outfit)?;
+
+    let outfit_template = lua.create_function(outfit::lua_template)?;
+    outfit.set("outfit_template", outfit_template)?;
+
+    let outfit_templates = lua.create_function(outfit::lua_templates)?;
+    outfit.set("outfit_templates", outfit_templates)?;
+
+    let batch = lua.create_function(batch::lua_batch)?;
+    outfit.set("batch", batch)?;
+
+    let build_item = lua.create_function(item::lua_build_item)?;
+    outfit.set("build_item", build_item)?;
+
+    let decode = lua.create_function(decode::lua_decode)?;
+    outfit.set("decode", decode)?;
+
+    let parse = lua.create_function(ast::lua_parse)?;
+    outfit.set("parse", parse)?;
+
+    let stringify = lua.create_function(ast::lua_stringify)?;
+    outfit.set("stringify", stringify)?;
+
+    let optimize = lua.create_function(optimize::lua_optimize)?;
+    outfit.set("optimize", optimize)?;
+
+    let render = lua.create_function(render::lua_render)?;
+    outfit.set("render", render)?;
+
+    let compare = lua.create_function(render::lua_compare)?;
+    outfit.set("compare", compare)?;
+
     Ok(outfit)
 }
@@ -0,0 +1,12 @@
+use crate::utils::{directives, image, template};
+
+pub fn generate(img: image::Image) -> anyhow::Result<String> {
+    let layout = template::sheet_directives(img.weight(), img.height())?;
+    let palette = template::sheet_palette(img)?;
+    Ok(layout + &directives::to_replace(palette, false))
+}
+
+pub fn lua_generate(_: &mlua::Lua, userdata: mlua::AnyUserData) -> mlua::Result<String> {
+    let img = image::read_image(userdata)?;
+    generate(img).map_err(mlua::Error::external)
+}
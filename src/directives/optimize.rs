@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+
+use indexmap::IndexMap;
+
+use super::ast::{Color, Operation, ScaleMode, parse};
+use crate::utils::image::to_hex;
+
+// Composes two replace operations into one, `first` runs before `second`
+fn merge_replace(first: &[(Color, Color)], second: &[(Color, Color)]) -> Vec<(Color, Color)> {
+    // Duplicate sources use the last mapping, like the game's color map
+    let first: IndexMap<Color, Color> = first.iter().copied().collect();
+    let second: IndexMap<Color, Color> = second.iter().copied().collect();
+
+    let mut merged: IndexMap<Color, Color> = first
+        .iter()
+        .map(|(from, to)| (*from, second.get(to).copied().unwrap_or(*to)))
+        .collect();
+    // Colors replaced by the first operation never reach the second one
+    for (from, to) in second {
+        merged.entry(from).or_insert(to);
+    }
+    merged.into_iter().collect()
+}
+
+fn is_noop(operation: &Operation) -> bool {
+    match operation {
+        Operation::Replace(colors) => colors.iter().all(|(from, to)| from == to),
+        Operation::Scale { x, y, .. } => *x == 1.0 && *y == 1.0,
+        Operation::Multiply(color) => color.0 == [255; 4],
+        Operation::Flip { x, y } => !x && !y,
+        Operation::HueShift(amount)
+        | Operation::Saturation(amount)
+        | Operation::Brightness(amount) => *amount == 0.0,
+        Operation::Fade { amount, .. } => *amount == 0.0,
+        _ => false,
+    }
+}
+
+// Colors that are known to be absent from the image, given the operations so far
+fn update_absent(absent: &mut HashSet<Color>, operation: &Operation) {
+    match operation {
+        Operation::Replace(colors) => {
+            let colors: IndexMap<Color, Color> = colors.iter().copied().collect();
+            for from in colors.keys() {
+                absent.insert(*from);
+            }
+            for to in colors.values() {
+                absent.remove(to);
+            }
+        }
+        // These only move existing pixels around
+        Operation::Flip { .. }
+        | Operation::Scale {
+            mode: ScaleMode::Nearest,
+            ..
+        } => {}
+        // Areas outside the image come back transparent
+        Operation::Crop { .. } => {
+            absent.remove(&Color([0; 4]));
+        }
+        _ => absent.clear(),
+    }
+}
+
+pub fn optimize(operations: Vec<Operation>) -> Vec<Operation> {
+    let mut output: Vec<Operation> = Vec::new();
+    let mut absent = HashSet::new();
+    // State before each output operation, restored when it gets merged away
+    let mut previous: Vec<HashSet<Color>> = Vec::new();
+
+    for operation in operations {
+        let operation = match operation {
+            Operation::Replace(colors) => Operation::Replace(
+                colors
+                    .into_iter()
+                    .filter(|(from, _)| !absent.contains(from))
+                    .collect(),
+            ),
+            operation => operation,
+        };
+        if is_noop(&operation) {
+            continue;
+        }
+
+        let operation = match (output.last(), operation) {
+            (Some(Operation::Replace(first)), Operation::Replace(second)) => {
+                let merged = merge_replace(first, &second);
+                output.pop();
+                absent = previous.pop().unwrap_or_default();
+                Operation::Replace(merged)
+            }
+            // Flipping the same axis twice cancels out
+            (Some(Operation::Flip { x, y }), Operation::Flip { x: x2, y: y2 }) => {
+                let flip = Operation::Flip {
+                    x: *x != x2,
+                    y: *y != y2,
+                };
+                output.pop();
+                absent = previous.pop().unwrap_or_default();
+                flip
+            }
+            (_, operation) => operation,
+        };
+
+        let operation = match operation {
+            Operation::Replace(colors) => {
+                Operation::Replace(colors.into_iter().filter(|(from, to)| from != to).collect())
+            }
+            operation => operation,
+        };
+        if is_noop(&operation) {
+            continue;
+        }
+
+        previous.push(absent.clone());
+        update_absent(&mut absent, &operation);
+        output.push(operation);
+    }
+    output
+}
+
+// Same as the `Display` output, but with colors in their shortest form
+fn minify(operation: &Operation) -> String {
+    let name = operation.name();
+    match operation {
+        Operation::Replace(colors) => {
+            let mut output = name.to_string();
+            for (from, to) in colors {
+                output.push_str(&format!(";{}={}", to_hex(&from.0), to_hex(&to.0)));
+            }
+            output
+        }
+        Operation::Multiply(color) | Operation::SetColor(color) => {
+            format!("{}={}", name, to_hex(&color.0))
+        }
+        Operation::Fade { color, amount } => format!("{}={}={}", name, to_hex(&color.0), amount),
+        Operation::Border {
+            size, start, end, ..
+        } => {
+            if start == end {
+                format!("{}={};{}", name, size, to_hex(&start.0))
+            } else {
+                format!("{}={};{};{}", name, size, to_hex(&start.0), to_hex(&end.0))
+            }
+        }
+        operation => operation.to_string(),
+    }
+}
+
+pub fn minify_all(operations: &[Operation]) -> String {
+    operations
+        .iter()
+        .map(|operation| format!("?{}", minify(operation)))
+        .collect()
+}
+
+// Returns the optimized directives and the number of bytes saved
+pub fn lua_optimize(_: &mlua::Lua, source: String) -> mlua::Result<(String, i64)> {
+    let operations = parse(&source).map_err(mlua::Error::external)?;
+    let optimized = minify_all(&optimize(operations));
+    let saved = source.len() as i64 - optimized.len() as i64;
+    Ok((optimized, saved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::*;
+    use super::super::outfit::{self, OutfitTemplate};
+    use super::super::render::Renderer;
+    use super::*;
+    use crate::utils::bitmap::Bitmap;
+    use crate::utils::image::Image;
+
+    // Renders the directives as parsed, optimized, and optimized and minified, on every base
+    fn check(directives: &str, bases: &[Bitmap]) {
+        let operations = parse(directives).unwrap();
+        let optimized = optimize(operations.clone());
+        let minified = parse(&minify_all(&optimized)).unwrap();
+        let mut renderer = Renderer::new(stub_asset);
+        for base in bases {
+            let expected = renderer.render(base, &operations).unwrap();
+            for (label, operations) in [("optimized", &optimized), ("minified", &minified)] {
+                let rendered = renderer.render(base, operations).unwrap();
+                assert_eq!(
+                    differences(&rendered, &expected),
+                    0,
+                    "{} {}: {}",
+                    label,
+                    directives,
+                    minify_all(operations)
+                );
+            }
+        }
+    }
+
+    // Red, green, blue and a transparent pixel, each side different so flips show
+    fn swatch() -> Bitmap {
+        let mut bitmap = Bitmap::new(3, 2);
+        let pixels = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [0, 0, 0, 0],
+            [255, 0, 0, 255],
+            [255, 255, 255, 128],
+        ];
+        for (index, pixel) in pixels.into_iter().enumerate() {
+            bitmap.set_pixel(index as u32 % 3, index as u32 / 3, pixel);
+        }
+        bitmap
+    }
+
+    #[test]
+    fn hand_made_directives_render_the_same() {
+        for directives in [
+            // Merged replace chains
+            "?replace;f00=0f0?replace;0f0=00f;00f=f00?replace;00f=fff",
+            "?replace;f00=0f0;f00=00f?replace;00f=0f0",
+            // a -> b, then b -> a
+            "?replace;f00=0f0?replace;0f0=f00",
+            "?replace;f00=0f0?flipx?replace;0f0=f00",
+            // Colors a replace removed can't be replaced again
+            "?replace;f00=0f0?replace;f00=00f",
+            "?replace;f00=0f0?scalenearest=2?replace;f00=00f;0f0=f00",
+            // Double flips
+            "?flipx?flipx",
+            "?flipx?flipy?flipxy",
+            "?flipxy?flipx?replace;f00=0f0",
+            // Replace after crop, which brings transparent pixels back
+            "?replace;0000=f00?crop;-1;-1;4;3?replace;0000=0f0",
+            "?replace;00000000=f00?crop;0;0;2;2?replace;00000000=00f",
+            "?crop;1;0;3;2?replace;f00=0f0?replace;0f0=f00",
+            // No-ops around the rest
+            "?multiply=fff?scale=1?replace;f00=f00?hueshift=0?replace;0f0=00f",
+        ] {
+            check(directives, &[swatch()]);
+        }
+    }
+
+    #[test]
+    fn bundled_outfits_render_the_same() {
+        for name in outfit::bundled_names() {
+            let template = OutfitTemplate::bundled(name).unwrap();
+            let variants = std::iter::once(None).chain(template.variants.keys().map(Some));
+            for variant in variants {
+                let variant = variant.map(String::as_str);
+                let sheets: Vec<Bitmap> = template
+                    .layers
+                    .iter()
+                    .map(|layer| sprite_sheet(&template, layer))
+                    .collect();
+                let mut buffers: Vec<Vec<u8>> = sheets.iter().map(bottom_up).collect();
+                let images: Vec<Image> = buffers
+                    .iter_mut()
+                    .zip(&sheets)
+                    .map(|(buffer, sheet)| image(buffer, sheet))
+                    .collect();
+                let generated = template.generate(&images, variant).unwrap();
+
+                // A few frames per template are enough, every frame shares the same chain
+                let bases: Vec<Bitmap> = if name == "hat" {
+                    vec![filled(43, 43, [0x80, 0x40, 0x20, 0xff])]
+                } else {
+                    let base = keyed_base(name, variant);
+                    let mut ids: Vec<u8> = template
+                        .layers
+                        .iter()
+                        .flat_map(|layer| layer.frames.iter().flatten())
+                        .filter_map(|id| u8::from_str_radix(id, 16).ok())
+                        .collect();
+                    ids.sort();
+                    ids.dedup();
+                    ids.into_iter().step_by(8).map(base).collect()
+                };
+                check(&generated.directives, &bases);
+            }
+        }
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::{BTreeSet, HashMap};
+
+use indexmap::IndexMap;
+
+use crate::asset::SBType;
+use crate::asset::jsonc;
+use crate::utils::{directives, image, template};
+
+const BUNDLED: [(&str, &str); 4] = [
+    ("pants", include_str!("templates/pants.json")),
+    ("chest", include_str!("templates/chest.json")),
+    ("back", include_str!("templates/back.json")),
+    ("hat", include_str!("templates/hat.json")),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    // Frame position and id as bytes, frames up to 256 pixels and 256 frame ids
+    Frame,
+    // Position on the whole sheet, any frame count, base directives are generated. Single layer
+    // only: the layout is built from a fixed 2x2 base that ignores the base sprite, so every layer
+    // would get the same colors
+    Sheet,
+    // Frame encoding base directives, extended so every frame id gets its own block of sheet
+    // cells. Layers work as with frames, frames go up to `template::MAX_CELL_FRAME_SIZE` pixels,
+    // frame ids are still limited to 256
+    Cells,
+}
+
+impl TryFrom<&str> for Encoding {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "frame" => Ok(Encoding::Frame),
+            "sheet" => Ok(Encoding::Sheet),
+            "cells" => Ok(Encoding::Cells),
+            _ => anyhow::bail!("Unknown template encoding '{}'", value),
+        }
+    }
+}
+
+// Diff warning raised while generating one template layer
+pub struct LayerWarning {
+    pub layer: String,
+    pub warning: image::DiffWarning,
+}
+
+impl From<&LayerWarning> for SBType {
+    fn from(value: &LayerWarning) -> Self {
+        let mut map = IndexMap::new();
+        map.insert("layer".to_string(), SBType::String(value.layer.clone()));
+        map.insert(
+            "message".to_string(),
+            SBType::String(value.warning.to_string()),
+        );
+        match &value.warning {
+            image::DiffWarning::Conflict {
+                from,
+                used,
+                ignored,
+            } => {
+                map.insert("kind".to_string(), SBType::String("conflict".to_string()));
+                map.insert("color".to_string(), SBType::String(from.clone()));
+                map.insert("used".to_string(), SBType::String(used.clone()));
+                map.insert(
+                    "ignored".to_string(),
+                    SBType::Array(ignored.iter().cloned().map(SBType::String).collect()),
+                );
+            }
+            image::DiffWarning::Outside { pixels, x, y } => {
+                map.insert("kind".to_string(), SBType::String("outside".to_string()));
+                map.insert("pixels".to_string(), SBType::Int(*pixels as i64));
+                map.insert("x".to_string(), SBType::Int(*x as i64));
+                map.insert("y".to_string(), SBType::Int(*y as i64));
+            }
+        }
+        SBType::Object(map)
+    }
+}
+
+pub fn warnings_value(warnings: &[LayerWarning]) -> SBType {
+    SBType::Array(warnings.iter().map(SBType::from).collect())
+}
+
+pub struct Generated {
+    pub directives: String,
+    pub warnings: Vec<LayerWarning>,
+}
+
+// One source image, laid out as a grid of frames
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub name: String,
+    // Rows top to bottom, an empty id leaves the frame out
+    pub frames: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutfitTemplate {
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub encoding: Encoding,
+    pub layers: Vec<Layer>,
+    pub directives: String,
+    // Alternative base directives, selected by name
+    pub variants: IndexMap<String, String>,
+    // Vanilla item the directives are made for
+    pub item_name: Option<String>,
+    pub inventory_icon: Option<String>,
+}
+
+fn parse_layer(value: &SBType, index: usize, encoding: Encoding) -> anyhow::Result<Layer> {
+    let name = value
+        .get("name")
+        .and_then(SBType::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| index.to_string());
+    let Some(rows) = value.get("frames").and_then(SBType::as_array) else {
+        anyhow::bail!("Layer '{}' has no frames", name);
+    };
+
+    let mut frames = Vec::new();
+    for row in rows {
+        let Some(row) = row.as_array() else {
+            anyhow::bail!("Layer '{}' frame rows must be arrays", name);
+        };
+        let mut ids = Vec::new();
+        for id in row {
+            let id = id.as_str().unwrap_or_default();
+            // Frame ids end up as the green channel of the template pixels
+            if encoding != Encoding::Sheet
+                && !id.is_empty()
+                && (id.len() > 2 || u8::from_str_radix(id, 16).is_err())
+            {
+                anyhow::bail!("Layer '{}' has invalid frame id '{}'", name, id);
+            }
+            ids.push(id.to_string());
+        }
+        frames.push(ids);
+    }
+
+    let columns = frames.first().map(Vec::len).unwrap_or(0);
+    if columns == 0 {
+        anyhow::bail!("Layer '{}' has no frames", name);
+    }
+    if frames.iter().any(|row| row.len() != columns) {
+        anyhow::bail!("Layer '{}' frame rows differ in length", name);
+    }
+    Ok(Layer { name, frames })
+}
+
+impl TryFrom<&SBType> for OutfitTemplate {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &SBType) -> Result<Self, Self::Error> {
+        let size: Vec<i64> = value
+            .get("frameSize")
+            .and_then(SBType::as_array)
+            .map(|size| size.iter().filter_map(SBType::as_i64).collect())
+            .unwrap_or_default();
+        let [frame_width, frame_height] = size[..] else {
+            anyhow::bail!("Template needs a frameSize of [width, height]");
+        };
+        let encoding = match value.get("encoding").and_then(SBType::as_str) {
+            Some(encoding) => Encoding::try_from(encoding)?,
+            None => Encoding::Frame,
+        };
+        let limit = match encoding {
+            Encoding::Frame => template::MAX_FRAME_SIZE,
+            Encoding::Sheet => template::MAX_SHEET_SIZE,
+            Encoding::Cells => template::MAX_CELL_FRAME_SIZE,
+        };
+        if !(1..=limit as i64).contains(&frame_width) || !(1..=limit as i64).contains(&frame_height)
+        {
+            anyhow::bail!(
+                "Frame size {}x{} is out of range, the limit is {}x{}",
+                frame_width,
+                frame_height,
+                limit,
+                limit
+            );
+        }
+        let (frame_width, frame_height) = (frame_width as u32, frame_height as u32);
+
+        let layers = value
+            .get("layers")
+            .and_then(SBType::as_array)
+            .map(|layers| {
+                layers
+                    .iter()
+                    .enumerate()
+                    .map(|(index, layer)| parse_layer(layer, index, encoding))
+                    .collect::<anyhow::Result<Vec<_>>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        if layers.is_empty() {
+            anyhow::bail!("Template has no layers");
+        }
+        // Every layer would get the same sheet colors
+        if encoding == Encoding::Sheet && layers.len() > 1 {
+            anyhow::bail!(
+                "Sheet templates have a single layer, the layout can't tell layers apart. Use the cells encoding for large multi-layer frames"
+            );
+        }
+
+        let item = value.get("item");
+        let item_field = |key: &str| {
+            item.and_then(|item| item.get(key))
+                .and_then(SBType::as_str)
+                .map(str::to_string)
+        };
+
+        let directives = value
+            .get("directives")
+            .and_then(SBType::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let mut variants = IndexMap::new();
+        if let Some(SBType::Object(map)) = value.get("variants") {
+            for (name, variant) in map {
+                match variant.as_str() {
+                    Some(variant) => {
+                        variants.insert(name.clone(), variant.to_string());
+                    }
+                    None => anyhow::bail!("Variant '{}' must be a directive string", name),
+                }
+            }
+        }
+
+        let template = Self {
+            frame_width,
+            frame_height,
+            encoding,
+            layers,
+            directives,
+            variants,
+            item_name: item_field("itemName"),
+            inventory_icon: item_field("inventoryIcon"),
+        };
+        if encoding == Encoding::Cells {
+            template.cell_blocks()?;
+        }
+        Ok(template)
+    }
+}
+
+impl OutfitTemplate {
+    pub fn bundled(name: &str) -> anyhow::Result<Self> {
+        Self::try_from(&bundled_value(name)?)
+    }
+
+    // Base directives followed by a single replace covering every layer
+    pub fn generate(
+        &self,
+        images: &[image::Image],
+        variant: Option<&str>,
+    ) -> anyhow::Result<Generated> {
+        if images.len() != self.layers.len() {
+            let layers: Vec<&str> = self
+                .layers
+                .iter()
+                .map(|layer| layer.name.as_str())
+                .collect();
+            anyhow::bail!(
+                "Template expects {} images ({}), got {}",
+                layers.len(),
+                layers.join(", "),
+                images.len()
+            );
+        }
+        let mut output = self.base_directives(variant)?;
+
+        if self.encoding == Encoding::Sheet {
+            return Ok(Generated {
+                directives: self.generate_sheet(&self.layers[0], images[0], output)?,
+                warnings: Vec::new(),
+            });
+        }
+
+        let blocks = match self.encoding {
+            Encoding::Cells => Some(self.cell_blocks()?),
+            _ => None,
+        };
+
+        let mut warnings = Vec::new();
+        for (index, (layer, img)) in self.layers.iter().zip(images).enumerate() {
+            let color_table = image::to_color_table(
+                *img,
+                image::ImageParseOptions {
+                    skip_transparent: true,
+                },
+            )
+            .map_err(|e| anyhow::anyhow!("Layer '{}': {}", layer.name, e))?;
+            let swaps = match &blocks {
+                Some(blocks) => self.cell_swaps(layer, color_table, blocks),
+                None => template::create(self.frame_width, self.frame_height, layer.frames.clone())
+                    .and_then(|template_table| image::diffrent(template_table, color_table)),
+            };
+            let diff = swaps.map_err(|e| anyhow::anyhow!("Layer '{}': {}", layer.name, e))?;
+            output += &directives::to_replace(diff.swaps, index > 0);
+            warnings.extend(diff.warnings.into_iter().map(|warning| LayerWarning {
+                layer: layer.name.clone(),
+                warning,
+            }));
+        }
+        Ok(Generated {
+            directives: output,
+            warnings,
+        })
+    }
+
+    fn generate_sheet(
+        &self,
+        layer: &Layer,
+        img: image::Image,
+        base: String,
+    ) -> anyhow::Result<String> {
+        let width = layer.frames[0].len() as u32 * self.frame_width;
+        let height = layer.frames.len() as u32 * self.frame_height;
+        if img.weight() != width || img.height() != height {
+            anyhow::bail!(
+                "Layer '{}' expects a {}x{} image, got {}x{}",
+                layer.name,
+                width,
+                height,
+                img.weight(),
+                img.height()
+            );
+        }
+        Ok(base + &directives::to_replace(template::sheet_palette(img)?, false))
+    }
+
+    // Everything the generated directives put before the colors, which builds the layout
+    pub(super) fn base_directives(&self, variant: Option<&str>) -> anyhow::Result<String> {
+        let base = match variant {
+            Some(variant) => self
+                .variants
+                .get(variant)
+                .ok_or_else(|| anyhow::anyhow!("Unknown template variant '{}'", variant))?
+                .clone(),
+            None => self.directives.clone(),
+        };
+        Ok(match self.encoding {
+            Encoding::Frame => base,
+            // Templates may bring their own chain that builds the same layout
+            Encoding::Sheet if base.is_empty() => {
+                let layer = &self.layers[0];
+                template::sheet_directives(
+                    layer.frames[0].len() as u32 * self.frame_width,
+                    layer.frames.len() as u32 * self.frame_height,
+                )?
+            }
+            Encoding::Sheet => base,
+            Encoding::Cells => {
+                let blocks = self.cell_blocks()?;
+                base + &template::cell_directives(self.frame_width, self.frame_height, &blocks)
+            }
+        })
+    }
+
+    // Cell block of every frame id used by the layers
+    pub(super) fn cell_blocks(&self) -> anyhow::Result<HashMap<u8, (u32, u32)>> {
+        let ids: BTreeSet<u8> = self
+            .layers
+            .iter()
+            .flat_map(|layer| layer.frames.iter().flatten())
+            .filter_map(|id| u8::from_str_radix(id, 16).ok())
+            .collect();
+        template::cell_blocks(self.frame_width, self.frame_height, &ids)
+    }
+
+    fn cell_swaps(
+        &self,
+        layer: &Layer,
+        color_table: Vec<Vec<String>>,
+        blocks: &HashMap<u8, (u32, u32)>,
+    ) -> anyhow::Result<image::Diff> {
+        let colors =
+            template::cell_colors(self.frame_width, self.frame_height, &layer.frames, blocks)?;
+        let template_table = colors
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|color| color.map(|color| image::to_hex(&color)).unwrap_or_default())
+                    .collect()
+            })
+            .collect();
+        let mut diff = image::diffrent(template_table, color_table.clone())?;
+        // Cells above the first row have a visible alpha, so transparent sprite pixels clear them
+        for (row, sprite_row) in colors.iter().zip(&color_table) {
+            for (color, sprite) in row.iter().zip(sprite_row) {
+                if let Some(color) = color
+                    && color[3] != 0
+                    && sprite.is_empty()
+                {
+                    diff.swaps
+                        .entry(image::to_hex(color))
+                        .or_insert_with(|| image::to_hex(&[0; 4]));
+                }
+            }
+        }
+        Ok(diff)
+    }
+}
+
+fn bundled_value(name: &str) -> anyhow::Result<SBType> {
+    let Some((_, source)) = BUNDLED.iter().find(|(bundled, _)| *bundled == name) else {
+        anyhow::bail!("Unknown outfit template '{}'", name);
+    };
+    jsonc::parse(source)
+}
+
+pub(super) fn template_value(value: &SBType) -> anyhow::Result<OutfitTemplate> {
+    match value {
+        SBType::String(name) => OutfitTemplate::bundled(name),
+        value => OutfitTemplate::try_from(value),
+    }
+}
+
+// `template` is a bundled template name or a template table, the warnings come second
+pub fn lua_generate(
+    _: &mlua::Lua,
+    (template, images, variant): (SBType, Vec<mlua::AnyUserData>, Option<String>),
+) -> mlua::Result<(String, SBType)> {
+    let template = template_value(&template).map_err(mlua::Error::external)?;
+    let images = images
+        .into_iter()
+        .map(image::read_image)
+        .collect::<mlua::Result<Vec<_>>>()?;
+    let generated = template
+        .generate(&images, variant.as_deref())
+        .map_err(mlua::Error::external)?;
+    Ok((generated.directives, warnings_value(&generated.warnings)))
+}
+
+// Definition of a bundled template, as a starting point for new ones
+pub fn lua_template(_: &mlua::Lua, name: String) -> mlua::Result<SBType> {
+    bundled_value(&name).map_err(mlua::Error::external)
+}
+
+pub fn bundled_names() -> Vec<&'static str> {
+    BUNDLED.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn lua_templates(_: &mlua::Lua, _: ()) -> mlua::Result<Vec<String>> {
+    Ok(bundled_names().into_iter().map(str::to_string).collect())
+}
@@ -0,0 +1,696 @@
+use std::collections::HashMap;
+
+use super::ast::{BlendMode, Color, MaskMode, Operation, ScaleMode, parse};
+use crate::asset::AssetReaderEnum;
+use crate::utils::bitmap::Bitmap;
+use crate::utils::template;
+
+// Directives come from untrusted sources, so every image they produce is capped. Sides are
+// limited like the sheet layout, the total so a single image stays within 256 MiB
+const MAX_IMAGE_SIDE: i64 = template::MAX_SHEET_SIZE as i64;
+const MAX_IMAGE_PIXELS: i64 = 1 << 26;
+// Each border pixel looks at a square of this size around it
+const MAX_BORDER_SIZE: u32 = 128;
+
+fn checked_size(
+    operation: &str,
+    width: Option<i64>,
+    height: Option<i64>,
+) -> anyhow::Result<(i64, i64)> {
+    let (width, height) = match (width, height) {
+        (Some(width), Some(height)) => (width.max(0), height.max(0)),
+        _ => (i64::MAX, i64::MAX),
+    };
+    if width > MAX_IMAGE_SIDE || height > MAX_IMAGE_SIDE || width * height > MAX_IMAGE_PIXELS {
+        anyhow::bail!(
+            "{} would make an image larger than the limit of {}x{} and {} pixels",
+            operation,
+            MAX_IMAGE_SIDE,
+            MAX_IMAGE_SIDE,
+            MAX_IMAGE_PIXELS
+        );
+    }
+    Ok((width, height))
+}
+
+// The game's images have their origin at the bottom left, so every operation here works on
+// bottom-up rows and the bitmap is only flipped on the way in and out
+struct Canvas {
+    width: i64,
+    height: i64,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl Canvas {
+    fn new(width: i64, height: i64) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[0; 4]; (width.max(0) * height.max(0)) as usize],
+        }
+    }
+
+    fn from_bitmap(bitmap: &Bitmap) -> Self {
+        let mut canvas = Self::new(bitmap.width as i64, bitmap.height as i64);
+        for y in 0..bitmap.height {
+            for x in 0..bitmap.width {
+                canvas.set(x as i64, (bitmap.height - 1 - y) as i64, bitmap.pixel(x, y));
+            }
+        }
+        canvas
+    }
+
+    fn to_bitmap(&self) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.width as u32, self.height as u32);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                bitmap.set_pixel(x as u32, (self.height - 1 - y) as u32, self.get(x, y));
+            }
+        }
+        bitmap
+    }
+
+    fn get(&self, x: i64, y: i64) -> [u8; 4] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn set(&mut self, x: i64, y: i64, pixel: [u8; 4]) {
+        self.pixels[(y * self.width + x) as usize] = pixel;
+    }
+
+    // Transparent outside the image
+    fn get_or_clear(&self, x: i64, y: i64) -> [u8; 4] {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            [0; 4]
+        } else {
+            self.get(x, y)
+        }
+    }
+
+    // Nearest edge pixel outside the image
+    fn clamp(&self, x: i64, y: i64) -> [u8; 4] {
+        if self.width == 0 || self.height == 0 {
+            return [0; 4];
+        }
+        self.get(x.clamp(0, self.width - 1), y.clamp(0, self.height - 1))
+    }
+
+    fn clamp_f(&self, x: i64, y: i64) -> [f32; 4] {
+        self.clamp(x, y).map(|c| c as f32)
+    }
+}
+
+fn map4(a: [f32; 4], f: impl Fn(usize, f32) -> f32) -> [f32; 4] {
+    [f(0, a[0]), f(1, a[1]), f(2, a[2]), f(3, a[3])]
+}
+
+fn to_pixel(value: [f32; 4]) -> [u8; 4] {
+    value.map(|c| c.clamp(0.0, 255.0) as u8)
+}
+
+fn lerp(t: f32, a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    map4(a, |i, a| a + (b[i] - a) * t)
+}
+
+fn cubic(t: f32, a: [f32; 4], b: [f32; 4], c: [f32; 4], d: [f32; 4]) -> [f32; 4] {
+    map4(a, |i, a| {
+        let a0 = d[i] - c[i] - a + b[i];
+        let a1 = a - b[i] - a0;
+        let a2 = c[i] - a;
+        let a3 = b[i];
+        a0 * t * t * t + a1 * t * t + a2 * t + a3
+    })
+}
+
+fn scale(canvas: &Canvas, mode: ScaleMode, scale_x: f64, scale_y: f64) -> anyhow::Result<Canvas> {
+    // Checked before the cast, which would saturate infinities and turn NaN into 0
+    let side = |size: i64, scale: f64| {
+        let side = (size as f64 * scale).round();
+        (side.is_finite() && side.abs() <= MAX_IMAGE_SIDE as f64).then(|| (side as i64).max(1))
+    };
+    let (width, height) = checked_size(
+        "Scale",
+        side(canvas.width, scale_x),
+        side(canvas.height, scale_y),
+    )?;
+    let mut output = Canvas::new(width, height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let px = (x as f64 / scale_x) as f32;
+            let py = (y as f64 / scale_y) as f32;
+            let (ix, iy) = (px.floor() as i64, py.floor() as i64);
+            let (fx, fy) = (px - ix as f32, py - iy as f32);
+
+            let pixel = match mode {
+                ScaleMode::Nearest => canvas.clamp(ix, iy),
+                ScaleMode::Bilinear => to_pixel(lerp(
+                    fy,
+                    lerp(fx, canvas.clamp_f(ix, iy), canvas.clamp_f(ix + 1, iy)),
+                    lerp(
+                        fx,
+                        canvas.clamp_f(ix, iy + 1),
+                        canvas.clamp_f(ix + 1, iy + 1),
+                    ),
+                )),
+                ScaleMode::Default => {
+                    let row = |dy: i64| {
+                        cubic(
+                            fx,
+                            canvas.clamp_f(ix - 1, iy + dy),
+                            canvas.clamp_f(ix, iy + dy),
+                            canvas.clamp_f(ix + 1, iy + dy),
+                            canvas.clamp_f(ix + 2, iy + dy),
+                        )
+                    };
+                    to_pixel(cubic(fy, row(-1), row(0), row(1), row(2)))
+                }
+            };
+            output.set(x, y, pixel);
+        }
+    }
+    Ok(output)
+}
+
+// HSV in 0..1, matching the game's Color class
+fn to_hsv(pixel: [u8; 4]) -> [f32; 3] {
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| c as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / delta + 2.0) / 6.0
+    } else {
+        ((r - g) / delta + 4.0) / 6.0
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    [hue, saturation, max]
+}
+
+fn from_hsv([hue, saturation, value]: [f32; 3], alpha: u8) -> [u8; 4] {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let c = value * saturation;
+    let x = c * (1.0 - (h.rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = value - c;
+    let channel = |c: f32| ((c + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    [channel(r), channel(g), channel(b), alpha]
+}
+
+fn border(
+    canvas: &Canvas,
+    size: u32,
+    start: Color,
+    end: Color,
+    outline: bool,
+) -> anyhow::Result<Canvas> {
+    if size > MAX_BORDER_SIZE {
+        anyhow::bail!(
+            "Border size {} is over the limit of {}",
+            size,
+            MAX_BORDER_SIZE
+        );
+    }
+    let size = size as i64;
+    let (width, height) = checked_size(
+        "Border",
+        Some(canvas.width + size * 2),
+        Some(canvas.height + size * 2),
+    )?;
+    let mut output = Canvas::new(width, height);
+    let start = start.0.map(|c| c as f32);
+    let end = end.0.map(|c| c as f32);
+
+    for y in 0..output.height {
+        for x in 0..output.width {
+            let pixel = canvas.get_or_clear(x - size, y - size);
+            if pixel[3] == 255 {
+                // Outlines leave the image itself out
+                output.set(x, y, if outline { [0; 4] } else { pixel });
+                continue;
+            }
+
+            // Manhattan distance to the closest visible pixel of the source
+            let mut distance = i64::MAX;
+            for j in -size..=size {
+                for i in -size..=size {
+                    if canvas.get_or_clear(x - size + i, y - size + j)[3] != 0 {
+                        distance = distance.min(i.abs() + j.abs());
+                    }
+                }
+            }
+            if distance == i64::MAX {
+                output.set(x, y, pixel);
+                continue;
+            }
+
+            let percent = ((distance - 1) as f32 / (2.0 * size as f32 - 1.0)).clamp(0.0, 1.0);
+            let mut color = lerp(percent, start, end);
+            if pixel[3] != 0 {
+                let alpha = pixel[3] as f32 / 255.0;
+                if outline {
+                    color[3] = (1.0 - alpha) * alpha.min(0.5) * 0.5 * 255.0;
+                } else {
+                    // Translucent pixels are drawn over the border color
+                    let color_alpha = color[3] / 255.0;
+                    let mixed_alpha = color_alpha + alpha * (1.0 - color_alpha);
+                    color = map4(color, |i, c| {
+                        if i == 3 {
+                            mixed_alpha * 255.0
+                        } else {
+                            pixel[i] as f32 + (c - pixel[i] as f32) * color_alpha
+                        }
+                    });
+                }
+            }
+            output.set(x, y, to_pixel(color.map(f32::round)));
+        }
+    }
+    Ok(output)
+}
+
+pub struct Renderer<F: FnMut(&str) -> anyhow::Result<Bitmap>> {
+    load: F,
+    images: HashMap<String, Canvas>,
+}
+
+impl<F: FnMut(&str) -> anyhow::Result<Bitmap>> Renderer<F> {
+    // `load` resolves images referenced by blend and mask operations
+    pub fn new(load: F) -> Self {
+        Self {
+            load,
+            images: HashMap::new(),
+        }
+    }
+
+    fn image(&mut self, path: &str) -> anyhow::Result<&Canvas> {
+        if !self.images.contains_key(path) {
+            let bitmap = (self.load)(path)
+                .map_err(|e| anyhow::anyhow!("Failed to load '{}': {}", path, e))?;
+            self.images
+                .insert(path.to_string(), Canvas::from_bitmap(&bitmap));
+        }
+        Ok(&self.images[path])
+    }
+
+    pub fn render(&mut self, image: &Bitmap, operations: &[Operation]) -> anyhow::Result<Bitmap> {
+        let mut canvas = Canvas::from_bitmap(image);
+        for operation in operations {
+            canvas = self.apply(canvas, operation)?;
+        }
+        Ok(canvas.to_bitmap())
+    }
+
+    fn apply(&mut self, mut canvas: Canvas, operation: &Operation) -> anyhow::Result<Canvas> {
+        match operation {
+            Operation::Replace(colors) => {
+                let colors: HashMap<[u8; 4], [u8; 4]> =
+                    colors.iter().map(|(from, to)| (from.0, to.0)).collect();
+                for pixel in canvas.pixels.iter_mut() {
+                    if let Some(replacement) = colors.get(pixel) {
+                        *pixel = *replacement;
+                    }
+                }
+            }
+            Operation::Scale { mode, x, y } => {
+                canvas = scale(&canvas, *mode, *x, *y)?;
+            }
+            Operation::Crop {
+                left,
+                bottom,
+                right,
+                top,
+            } => {
+                let (width, height) =
+                    checked_size("Crop", right.checked_sub(*left), top.checked_sub(*bottom))?;
+                let mut output = Canvas::new(width, height);
+                for y in 0..output.height {
+                    for x in 0..output.width {
+                        output.set(x, y, canvas.get_or_clear(left + x, bottom + y));
+                    }
+                }
+                canvas = output;
+            }
+            Operation::Blend { mode, image, x, y } => {
+                let (offset_x, offset_y) = (*x, *y);
+                let blend = self.image(image)?;
+                for py in 0..canvas.height {
+                    for px in 0..canvas.width {
+                        let other = blend
+                            .clamp(px.saturating_add(offset_x), py.saturating_add(offset_y))
+                            .map(|c| c as u32);
+                        let pixel = canvas.get(px, py).map(|c| c as u32);
+                        let result = match mode {
+                            BlendMode::Multiply => {
+                                [0, 1, 2, 3].map(|i| (pixel[i] * other[i] / 255) as u8)
+                            }
+                            BlendMode::Screen => [0, 1, 2, 3]
+                                .map(|i| (255 - (255 - pixel[i]) * (255 - other[i]) / 255) as u8),
+                        };
+                        canvas.set(px, py, result);
+                    }
+                }
+            }
+            Operation::Mask { mode, image, x, y } => {
+                // Several masks can be joined with '+', their union is used
+                let paths: Vec<&str> = image.split('+').collect();
+                let mut mask = vec![0u8; canvas.pixels.len()];
+                for path in paths {
+                    let mask_image = self.image(path)?;
+                    for py in 0..canvas.height {
+                        for px in 0..canvas.width {
+                            let index = (py * canvas.width + px) as usize;
+                            let alpha = mask_image
+                                .get_or_clear(px.saturating_add(*x), py.saturating_add(*y))[3];
+                            mask[index] = mask[index].max(alpha);
+                        }
+                    }
+                }
+                for (pixel, alpha) in canvas.pixels.iter_mut().zip(mask) {
+                    pixel[3] = match mode {
+                        MaskMode::Add => pixel[3].min(alpha),
+                        MaskMode::Subtract => pixel[3].saturating_sub(alpha),
+                    };
+                }
+            }
+            Operation::Multiply(color) => {
+                for pixel in canvas.pixels.iter_mut() {
+                    *pixel =
+                        [0, 1, 2, 3].map(|i| (pixel[i] as u32 * color.0[i] as u32 / 255) as u8);
+                }
+            }
+            Operation::Flip { x, y } => {
+                let mut output = Canvas::new(canvas.width, canvas.height);
+                for py in 0..canvas.height {
+                    for px in 0..canvas.width {
+                        let sx = if *x { canvas.width - 1 - px } else { px };
+                        let sy = if *y { canvas.height - 1 - py } else { py };
+                        output.set(px, py, canvas.get(sx, sy));
+                    }
+                }
+                canvas = output;
+            }
+            Operation::HueShift(degrees) => {
+                let shift = (*degrees / 360.0) as f32;
+                for pixel in canvas.pixels.iter_mut().filter(|p| p[3] != 0) {
+                    let [h, s, v] = to_hsv(*pixel);
+                    *pixel = from_hsv([h + shift, s, v], pixel[3]);
+                }
+            }
+            Operation::Saturation(amount) => {
+                let shift = (*amount / 100.0) as f32;
+                for pixel in canvas.pixels.iter_mut().filter(|p| p[3] != 0) {
+                    let [h, s, v] = to_hsv(*pixel);
+                    *pixel = from_hsv([h, (s + shift).clamp(0.0, 1.0), v], pixel[3]);
+                }
+            }
+            Operation::Brightness(amount) => {
+                let multiply = (*amount / 100.0 + 1.0) as f32;
+                for pixel in canvas.pixels.iter_mut().filter(|p| p[3] != 0) {
+                    let [h, s, v] = to_hsv(*pixel);
+                    *pixel = from_hsv([h, s, (v * multiply).clamp(0.0, 1.0)], pixel[3]);
+                }
+            }
+            Operation::Fade { color, amount } => {
+                let amount = *amount as f32;
+                for pixel in canvas.pixels.iter_mut() {
+                    for (channel, target) in pixel.iter_mut().zip(color.0).take(3) {
+                        let value = *channel as f32;
+                        *channel = (value + (target as f32 - value) * amount)
+                            .round()
+                            .clamp(0.0, 255.0) as u8;
+                    }
+                }
+            }
+            Operation::Border {
+                outline,
+                size,
+                start,
+                end,
+            } => {
+                canvas = border(&canvas, *size, *start, *end, *outline)?;
+            }
+            Operation::SetColor(color) => {
+                for pixel in canvas.pixels.iter_mut() {
+                    pixel[..3].copy_from_slice(&color.0[..3]);
+                }
+            }
+        }
+        Ok(canvas)
+    }
+}
+
+// Number of pixels that differ, dimensions have to match
+pub fn compare(a: &Bitmap, b: &Bitmap) -> anyhow::Result<usize> {
+    if a.width != b.width || a.height != b.height {
+        anyhow::bail!(
+            "Image sizes differ: {}x{} and {}x{}",
+            a.width,
+            a.height,
+            b.width,
+            b.height
+        );
+    }
+    Ok(a.data
+        .chunks_exact(4)
+        .zip(b.data.chunks_exact(4))
+        .filter(|(a, b)| a != b)
+        .count())
+}
+
+// Asset paths win when a reader is given, anything else is read from disk
+fn load_image(reader: Option<&AssetReaderEnum>, path: &str) -> anyhow::Result<Bitmap> {
+    // Frame selectors and directives on referenced images are not applied
+    let file = path.split(['?', ':']).next().unwrap_or(path);
+    let bytes = match reader {
+        Some(reader) if reader.exist(file) => reader.read_bytes(file)?,
+        _ => std::fs::read(file)?,
+    };
+    Bitmap::from_png(&bytes)
+}
+
+pub fn lua_render(
+    lua: &mlua::Lua,
+    (image, directives, output, reader): (String, String, String, Option<mlua::AnyUserData>),
+) -> mlua::Result<mlua::Table> {
+    let reader = reader
+        .map(|reader| reader.borrow::<AssetReaderEnum>().map(|r| r.clone()))
+        .transpose()?;
+    let operations = parse(&directives).map_err(mlua::Error::external)?;
+
+    let source = load_image(reader.as_ref(), &image).map_err(mlua::Error::external)?;
+    let mut renderer = Renderer::new(|path: &str| load_image(reader.as_ref(), path));
+    let result = renderer
+        .render(&source, &operations)
+        .map_err(mlua::Error::external)?;
+    std::fs::write(&output, result.to_png().map_err(mlua::Error::external)?)?;
+
+    let table = lua.create_table()?;
+    table.set("width", result.width)?;
+    table.set("height", result.height)?;
+    Ok(table)
+}
+
+pub fn lua_compare(_: &mlua::Lua, (a, b): (String, String)) -> mlua::Result<usize> {
+    let a = Bitmap::from_png(&std::fs::read(a)?).map_err(mlua::Error::external)?;
+    let b = Bitmap::from_png(&std::fs::read(b)?).map_err(mlua::Error::external)?;
+    compare(&a, &b).map_err(mlua::Error::external)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::*;
+    use super::super::normal;
+    use super::super::outfit::{Encoding, OutfitTemplate};
+    use super::*;
+    use crate::asset::SBType;
+    use crate::utils::image::Image;
+
+    // Renders every frame of the generated directives on a base frame and compares it with the
+    // source sprite sheets. `base` builds the vanilla frame for a frame id
+    fn check_template(name: &str, variant: Option<&str>, base: impl Fn(u8) -> Bitmap) {
+        check_generated(&OutfitTemplate::bundled(name).unwrap(), name, variant, base);
+    }
+
+    fn check_generated(
+        template: &OutfitTemplate,
+        name: &str,
+        variant: Option<&str>,
+        base: impl Fn(u8) -> Bitmap,
+    ) {
+        let sheets: Vec<Bitmap> = template
+            .layers
+            .iter()
+            .map(|layer| sprite_sheet(template, layer))
+            .collect();
+        let mut buffers: Vec<Vec<u8>> = sheets.iter().map(bottom_up).collect();
+        let images: Vec<Image> = buffers
+            .iter_mut()
+            .zip(&sheets)
+            .map(|(buffer, sheet)| image(buffer, sheet))
+            .collect();
+
+        let generated = template.generate(&images, variant).unwrap();
+        assert!(generated.warnings.is_empty());
+        let operations = parse(&generated.directives).unwrap();
+
+        let (frame_width, frame_height) = (template.frame_width, template.frame_height);
+        let mut renderer = Renderer::new(stub_asset);
+        for (layer, sheet) in template.layers.iter().zip(&sheets) {
+            for (row, ids) in layer.frames.iter().enumerate() {
+                for (column, id) in ids.iter().enumerate() {
+                    let Ok(id) = u8::from_str_radix(id, 16) else {
+                        continue;
+                    };
+                    let rendered = renderer.render(&base(id), &operations).unwrap();
+                    let expected = crop(
+                        sheet,
+                        column as u32 * frame_width,
+                        row as u32 * frame_height,
+                        frame_width,
+                        frame_height,
+                    );
+                    assert_eq!(
+                        differences(&rendered, &expected),
+                        0,
+                        "{} layer '{}' frame {:02x}",
+                        name,
+                        layer.name,
+                        id
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pants_render_back_to_the_sprite() {
+        check_template("pants", None, keyed_base("pants", None));
+    }
+
+    #[test]
+    fn pants_hide_body_render_back_to_the_sprite() {
+        check_template(
+            "pants",
+            Some("hideBody"),
+            keyed_base("pants", Some("hideBody")),
+        );
+    }
+
+    #[test]
+    fn chest_renders_back_to_the_sprite() {
+        check_template("chest", None, keyed_base("chest", None));
+    }
+
+    #[test]
+    fn back_renders_back_to_the_sprite() {
+        check_template("back", None, keyed_base("back", None));
+    }
+
+    #[test]
+    fn hat_renders_back_to_the_sprite() {
+        check_template("hat", None, |_| filled(43, 43, [0x80, 0x40, 0x20, 0xff]));
+    }
+
+    #[test]
+    fn chest_cells_render_back_to_the_sprite() {
+        // Frames wider than a cell, on every chest layer
+        let mut template = OutfitTemplate::bundled("chest").unwrap();
+        template.encoding = Encoding::Cells;
+        (template.frame_width, template.frame_height) = (130, 20);
+        check_generated(&template, "chest cells", None, keyed_base("chest", None));
+    }
+
+    #[test]
+    fn pants_hide_body_cells_render_back_to_the_sprite() {
+        let mut template = OutfitTemplate::bundled("pants").unwrap();
+        template.encoding = Encoding::Cells;
+        (template.frame_width, template.frame_height) = (20, 130);
+        check_generated(
+            &template,
+            "pants cells",
+            Some("hideBody"),
+            keyed_base("pants", Some("hideBody")),
+        );
+    }
+
+    fn check_sheet(sheet: &Bitmap, directives: &str) {
+        let base = filled(43, 43, [0x80, 0x40, 0x20, 0xff]);
+        let rendered = Renderer::new(stub_asset)
+            .render(&base, &parse(directives).unwrap())
+            .unwrap();
+        assert_eq!(differences(&rendered, sheet), 0);
+    }
+
+    #[test]
+    fn image_sheet_renders_back_to_the_sprite() {
+        // Crosses cell borders in both directions
+        let mut sheet = Bitmap::new(300, 200);
+        for y in 0..sheet.height {
+            for x in 0..sheet.width {
+                sheet.set_pixel(x, y, sprite_color(y * sheet.width + x));
+            }
+        }
+        let mut buffer = bottom_up(&sheet);
+        let directives = normal::generate(image(&mut buffer, &sheet)).unwrap();
+        check_sheet(&sheet, &directives);
+    }
+
+    #[test]
+    fn sheet_template_renders_back_to_the_sprite() {
+        let value = SBType::try_from(
+            json::parse(
+                r#"{
+                    "frameSize": [150, 100],
+                    "encoding": "sheet",
+                    "layers": [{ "name": "sprite", "frames": [["1", "2"], ["3", ""]] }]
+                }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let template = OutfitTemplate::try_from(&value).unwrap();
+        let sheet = sprite_sheet(&template, &template.layers[0]);
+        let mut buffer = bottom_up(&sheet);
+        let generated = template
+            .generate(&[image(&mut buffer, &sheet)], None)
+            .unwrap();
+        check_sheet(&sheet, &generated.directives);
+    }
+
+    #[test]
+    fn oversized_directives_are_errors() {
+        let base = filled(4, 4, [0xff; 4]);
+        for directives in [
+            "?crop;0;0;1000000000;1000000000",
+            "?crop;-9223372036854775808;0;9223372036854775807;1",
+            "?scale=1000000000",
+            "?scalenearest=1e300;1",
+            "?border=100000;fff;000",
+            "?scale=4096?crop;0;0;16000;16000",
+        ] {
+            let result = Renderer::new(stub_asset).render(&base, &parse(directives).unwrap());
+            assert!(result.is_err(), "{} rendered", directives);
+        }
+        // Offsets far outside the blended image only clamp
+        let rendered = Renderer::new(stub_asset)
+            .render(
+                &base,
+                &parse("?blendmult=/dungeons/other/wreck/key.png;9223372036854775807;0").unwrap(),
+            )
+            .unwrap();
+        assert_eq!((rendered.width, rendered.height), (4, 4));
+    }
+}
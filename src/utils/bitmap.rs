@@ -0,0 +1,148 @@
+use png::{BitDepth, ColorType, Transformations};
+
+// Owned RGBA8 image with the origin at the top left, as stored in PNG files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bitmap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Bitmap {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; (width as usize) * (height as usize) * 4],
+        }
+    }
+
+    pub fn from_png(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        buffer.truncate(info.buffer_size());
+
+        let data = match info.color_type {
+            ColorType::Rgba => buffer,
+            ColorType::Rgb => buffer
+                .chunks_exact(3)
+                .flat_map(|c| [c[0], c[1], c[2], 255])
+                .collect(),
+            ColorType::GrayscaleAlpha => buffer
+                .chunks_exact(2)
+                .flat_map(|c| [c[0], c[0], c[0], c[1]])
+                .collect(),
+            ColorType::Grayscale => buffer.iter().flat_map(|&c| [c, c, c, 255]).collect(),
+            ColorType::Indexed => anyhow::bail!("Indexed PNG was not expanded"),
+        };
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            data,
+        })
+    }
+
+    pub fn to_png(&self) -> anyhow::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut output, self.width, self.height);
+            encoder.set_color(ColorType::Rgba);
+            encoder.set_depth(BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.data)?;
+        }
+        Ok(output)
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        ((y as usize) * (self.width as usize) + (x as usize)) * 4
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = self.offset(x, y);
+        [
+            self.data[offset],
+            self.data[offset + 1],
+            self.data[offset + 2],
+            self.data[offset + 3],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        let offset = self.offset(x, y);
+        self.data[offset..offset + 4].copy_from_slice(&pixel);
+    }
+
+    // Source-over blend of a single pixel
+    pub fn blend_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        let source_alpha = pixel[3] as u32;
+        if source_alpha == 0 {
+            return;
+        }
+        if source_alpha == 255 {
+            self.set_pixel(x, y, pixel);
+            return;
+        }
+
+        let target = self.pixel(x, y);
+        let target_alpha = target[3] as u32 * (255 - source_alpha) / 255;
+        let alpha = source_alpha + target_alpha;
+        let mut result = [0u8; 4];
+        for i in 0..3 {
+            result[i] =
+                ((pixel[i] as u32 * source_alpha + target[i] as u32 * target_alpha) / alpha) as u8;
+        }
+        result[3] = alpha as u8;
+        self.set_pixel(x, y, result);
+    }
+
+    // Draws `source` with its top left corner at (x, y), clipped to this bitmap
+    pub fn draw(&mut self, source: &Bitmap, x: i64, y: i64) {
+        for sy in 0..source.height {
+            let ty = y + sy as i64;
+            if ty < 0 || ty >= self.height as i64 {
+                continue;
+            }
+            for sx in 0..source.width {
+                let tx = x + sx as i64;
+                if tx < 0 || tx >= self.width as i64 {
+                    continue;
+                }
+                self.blend_pixel(tx as u32, ty as u32, source.pixel(sx, sy));
+            }
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: i64, y: i64, width: u32, height: u32, pixel: [u8; 4]) {
+        for ty in y.max(0)..(y + height as i64).min(self.height as i64) {
+            for tx in x.max(0)..(x + width as i64).min(self.width as i64) {
+                self.blend_pixel(tx as u32, ty as u32, pixel);
+            }
+        }
+    }
+
+    // Average color of the opaque pixels, used for flat previews
+    pub fn average_color(&self) -> Option<[u8; 4]> {
+        let mut sum = [0u64; 3];
+        let mut count = 0u64;
+        for pixel in self.data.chunks_exact(4).filter(|p| p[3] > 0) {
+            for i in 0..3 {
+                sum[i] += pixel[i] as u64;
+            }
+            count += 1;
+        }
+        (count > 0).then(|| {
+            [
+                (sum[0] / count) as u8,
+                (sum[1] / count) as u8,
+                (sum[2] / count) as u8,
+                255,
+            ]
+        })
+    }
+}
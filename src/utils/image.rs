@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use indexmap::IndexMap;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct Image {
@@ -53,43 +55,53 @@ impl Image {
         }
     }
 
-    pub fn get_pixel(&self, x: u32, y: u32) -> [u8; 4] {
+    // Only 8-bit channels are read, float images would need a conversion
+    pub fn check_format(&self) -> anyhow::Result<()> {
+        match self.format {
+            PixelFormat::RGBF | PixelFormat::RGBAF => anyhow::bail!(
+                "Unsupported pixel format {:?}, images need 8-bit channels",
+                self.format
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> anyhow::Result<[u8; 4]> {
+        self.check_format()?;
         let offset = ((y * self.width + x) * self.bytes_per_pixel() as u32) as usize;
         let mut pixel: [u8; 4] = [0; 4];
-        match self.bytes_per_pixel() {
-            4 => unsafe {
+        if self.bytes_per_pixel() == 4 {
+            unsafe {
                 pixel[0] = *self.data.add(offset);
                 pixel[1] = *self.data.add(offset + 1);
                 pixel[2] = *self.data.add(offset + 2);
                 pixel[3] = *self.data.add(offset + 3);
-            },
-
-            3 => {
-                pixel[0] = unsafe { *self.data.add(offset) };
-                pixel[1] = unsafe { *self.data.add(offset + 1) };
-                pixel[2] = unsafe { *self.data.add(offset + 2) };
-                pixel[3] = 255;
             }
-            _ => panic!("Unsupported bytes per pixel"),
+        } else {
+            pixel[0] = unsafe { *self.data.add(offset) };
+            pixel[1] = unsafe { *self.data.add(offset + 1) };
+            pixel[2] = unsafe { *self.data.add(offset + 2) };
+            pixel[3] = 255;
         }
-        pixel
+        Ok(pixel)
     }
 }
 
-pub fn read_image(userdata: mlua::AnyUserData) -> Image {
+pub fn read_image(userdata: mlua::AnyUserData) -> mlua::Result<Image> {
     let img: &Image;
     unsafe {
         let raw_ptr = userdata.to_pointer() as *const Image;
         img = &*raw_ptr;
     }
-    *img
+    img.check_format().map_err(mlua::Error::external)?;
+    Ok(*img)
 }
 
-pub fn to_color_table(img: Image, options: ImageParseOptions) -> Vec<Vec<String>> {
+pub fn to_color_table(img: Image, options: ImageParseOptions) -> anyhow::Result<Vec<Vec<String>>> {
     let mut rows = vec![vec!["".to_string(); img.weight() as usize]; img.height() as usize];
     for y in 0..img.height() {
         for x in 0..img.weight() {
-            let pixel = img.get_pixel(x, y);
+            let pixel = img.get_pixel(x, y)?;
             if options.skip_transparent && pixel[3] == 0 {
                 continue;
             }
@@ -97,7 +109,7 @@ pub fn to_color_table(img: Image, options: ImageParseOptions) -> Vec<Vec<String>
             rows[(img.height() - 1 - y) as usize][x as usize] = hex;
         }
     }
-    rows
+    Ok(rows)
 }
 
 pub fn to_hex(arr: &[u8]) -> String {
@@ -119,16 +131,178 @@ pub fn to_hex(arr: &[u8]) -> String {
     s
 }
 
-pub fn diffrent(from: Vec<Vec<String>>, to: Vec<Vec<String>>) -> HashMap<String, String> {
+pub enum DiffWarning {
+    // Template color drawn with several sprite colors, only `used` ends up in the outfit
+    Conflict {
+        from: String,
+        used: String,
+        ignored: Vec<String>,
+    },
+    // Visible sprite pixels where the template has no frame, counted from the top left
+    Outside {
+        pixels: usize,
+        x: usize,
+        y: usize,
+    },
+}
+
+impl std::fmt::Display for DiffWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffWarning::Conflict {
+                from,
+                used,
+                ignored,
+            } => write!(
+                f,
+                "Template color {} is drawn as {} and {}, using {}",
+                from,
+                used,
+                ignored.join(", "),
+                used
+            ),
+            DiffWarning::Outside { pixels, x, y } => write!(
+                f,
+                "{} sprite pixels are outside the template frames and get dropped, the first at {},{}",
+                pixels, x, y
+            ),
+        }
+    }
+}
+
+pub struct Diff {
+    pub swaps: HashMap<String, String>,
+    pub warnings: Vec<DiffWarning>,
+}
+
+pub fn diffrent(from: Vec<Vec<String>>, to: Vec<Vec<String>>) -> anyhow::Result<Diff> {
+    let size = |table: &Vec<Vec<String>>| (table.first().map(Vec::len).unwrap_or(0), table.len());
+    if size(&from) != size(&to) || to.iter().any(|row| row.len() != size(&from).0) {
+        let ((width, height), (sprite_width, sprite_height)) = (size(&from), size(&to));
+        anyhow::bail!(
+            "Sprite is {}x{}, the template expects {}x{}",
+            sprite_width,
+            sprite_height,
+            width,
+            height
+        );
+    }
+
     let mut swaps = HashMap::new();
-    for (y, row) in from.iter().enumerate() {
-        for (x, a) in row.iter().enumerate() {
-            let b = to[y][x].clone();
-            if b.is_empty() || a.is_empty() {
+    // Template color -> every sprite color drawn over it, in order of appearance
+    let mut drawn: IndexMap<&String, Vec<&String>> = IndexMap::new();
+    let mut outside: Option<(usize, usize, usize)> = None;
+    for (y, (row, sprite_row)) in from.iter().zip(&to).enumerate() {
+        for (x, (a, b)) in row.iter().zip(sprite_row).enumerate() {
+            if b.is_empty() {
                 continue;
             }
-            swaps.insert(a.clone(), b);
+            if a.is_empty() {
+                let (pixels, _, _) = outside.get_or_insert((0, x, y));
+                *pixels += 1;
+                continue;
+            }
+            let colors = drawn.entry(a).or_default();
+            if !colors.contains(&b) {
+                colors.push(b);
+            }
+            swaps.insert(a.clone(), b.clone());
+        }
+    }
+
+    let mut warnings: Vec<DiffWarning> = drawn
+        .into_iter()
+        .filter(|(_, colors)| colors.len() > 1)
+        .map(|(from, colors)| {
+            let used = swaps[from].clone();
+            DiffWarning::Conflict {
+                from: from.clone(),
+                ignored: colors
+                    .into_iter()
+                    .filter(|color| **color != used)
+                    .cloned()
+                    .collect(),
+                used,
+            }
+        })
+        .collect();
+    if let Some((pixels, x, y)) = outside {
+        warnings.push(DiffWarning::Outside { pixels, x, y });
+    }
+
+    Ok(Diff { swaps, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn wrongly_sized_sprites_are_errors() {
+        let template = table(&[&["a", "b"], &["c", "d"]]);
+        for sprite in [
+            table(&[&["1", "2", "3"], &["4", "5", "6"]]),
+            table(&[&["1", "2"]]),
+            table(&[&["1", "2"], &["3"]]),
+        ] {
+            let error = diffrent(template.clone(), sprite).err().unwrap();
+            assert!(error.to_string().contains("the template expects 2x2"));
+        }
+    }
+
+    #[test]
+    fn colors_drawn_twice_are_conflicts() {
+        let diff = diffrent(
+            table(&[&["a", "a", "b"], &["a", "b", ""]]),
+            table(&[&["1", "2", "3"], &["1", "3", ""]]),
+        )
+        .unwrap();
+        assert_eq!(diff.swaps["a"], "1");
+        assert_eq!(diff.swaps["b"], "3");
+        let [
+            DiffWarning::Conflict {
+                from,
+                used,
+                ignored,
+            },
+        ] = &diff.warnings[..]
+        else {
+            panic!("expected one conflict, got {}", diff.warnings.len());
+        };
+        assert_eq!((from.as_str(), used.as_str()), ("a", "1"));
+        assert_eq!(ignored, &["2"]);
+    }
+
+    #[test]
+    fn pixels_outside_the_frames_are_reported() {
+        let diff = diffrent(
+            table(&[&["a", ""], &["", "b"]]),
+            table(&[&["1", ""], &["2", "3"]]),
+        )
+        .unwrap();
+        assert_eq!(diff.swaps.len(), 2);
+        let [DiffWarning::Outside { pixels, x, y }] = &diff.warnings[..] else {
+            panic!("expected one outside warning, got {}", diff.warnings.len());
+        };
+        assert_eq!((*pixels, *x, *y), (1, 0, 1));
+    }
+
+    #[test]
+    fn float_images_are_errors() {
+        let mut data = [0u8; 16];
+        for format in [PixelFormat::RGBF, PixelFormat::RGBAF] {
+            let img = Image::new(data.as_mut_ptr(), 1, 1, format);
+            assert!(img.get_pixel(0, 0).is_err());
+            let options = ImageParseOptions {
+                skip_transparent: false,
+            };
+            assert!(to_color_table(img, options).is_err());
         }
     }
-    swaps
 }
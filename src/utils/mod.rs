@@ -1,3 +1,4 @@
+pub mod bitmap;
 pub mod directives;
 pub mod image;
 pub mod template;
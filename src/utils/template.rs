@@ -1,42 +1,302 @@
-use crate::utils::image::to_hex;
+use std::collections::{BTreeSet, HashMap};
 
-pub fn create(frame_width: u8, frame_height: u8, frames: Vec<Vec<String>>) -> Vec<Vec<String>> {
-    let vertical_frames = frames.len() as u8;
-    let horizontal_frames = frames[0].len() as u8;
+use crate::utils::image::{self, to_hex};
 
-    let v = (vertical_frames as usize) * (frame_height as usize);
+// Frame templates store the pixel position inside a frame and the frame id as single bytes
+pub const MAX_FRAME_SIZE: u32 = 256;
+
+// Sheet templates count in cells, a power of two keeps the interpolated values exact
+pub const CELL_SIZE: u32 = 128;
+// Largest size the sheet layout can encode, 127 cells along each side
+pub const MAX_SHEET_SIZE: u32 = CELL_SIZE * 127;
+
+// Same 2x2 start as the hat template: four distinct colors on any mostly opaque base image
+const SHEET_BASE_DIRECTIVES: &str = "?setcolor=fff?replace;fff0=fff?crop;0;0;2;2?blendmult=/items/active/weapons/protectorate/aegisaltpistol/beamend.png;0;0";
+// Base colors at (0, 0), (1, 0), (0, 1) and (1, 1), counted from the bottom left
+const SHEET_BASE_COLORS: [[u8; 4]; 4] = [
+    [0xa3, 0x55, 0xc0, 0xa5],
+    [0xa3, 0x55, 0xc0, 0x7b],
+    [0xff, 0xff, 0xff, 0xa5],
+    [0xff, 0xff, 0xff, 0x7b],
+];
+
+fn frame_id(id: &str) -> anyhow::Result<u8> {
+    u8::from_str_radix(id, 16)
+        .map_err(|_| anyhow::anyhow!("Invalid frame id '{}', expected a hex byte", id))
+}
+
+pub fn create(
+    frame_width: u32,
+    frame_height: u32,
+    frames: Vec<Vec<String>>,
+) -> anyhow::Result<Vec<Vec<String>>> {
+    if frame_width > MAX_FRAME_SIZE || frame_height > MAX_FRAME_SIZE {
+        anyhow::bail!(
+            "Frame size {}x{} is too large for a frame template, the limit is {}x{}. The cells encoding takes larger frames",
+            frame_width,
+            frame_height,
+            MAX_FRAME_SIZE,
+            MAX_FRAME_SIZE
+        );
+    }
+    let vertical_frames = frames.len();
+    let horizontal_frames = frames.first().map(Vec::len).unwrap_or(0);
+    if frames.iter().any(|row| row.len() != horizontal_frames) {
+        anyhow::bail!("Frame rows differ in length");
+    }
+
+    let (frame_width, frame_height) = (frame_width as usize, frame_height as usize);
+    let v = vertical_frames * frame_height;
     // Build arrray
-    let mut rows = vec![
-        vec![String::new(); (horizontal_frames as usize) * (frame_width as usize)];
-        v as usize
-    ];
+    let mut rows = vec![vec![String::new(); horizontal_frames * frame_width]; v];
 
     // For every frame
     for w in 0..horizontal_frames {
         for h in 0..vertical_frames {
             // Frame identifier
-            let id = &frames[(vertical_frames as usize) - 1 - (h as usize)][w as usize];
+            let id = &frames[vertical_frames - 1 - h][w];
             if id.is_empty() {
                 continue;
             }
+            let id = frame_id(id)?;
 
             // For every pixel in frame
             for x in 0..frame_width {
                 for y in 0..frame_height {
-                    let pixel = to_hex(&[
-                        x.try_into().unwrap(),
-                        u8::from_str_radix(id, 16).unwrap(),
-                        y.try_into().unwrap(),
-                        0,
-                    ]);
-                    rows[(v as usize)
-                        - 1
-                        - ((h as usize) * (frame_height as usize) + (y as usize))]
-                        [(w as usize) * (frame_width as usize) + (x as usize)] = pixel;
+                    let pixel = to_hex(&[x as u8, id, y as u8, 0]);
+                    rows[v - 1 - (h * frame_height + y)][w * frame_width + x] = pixel;
+                }
+            }
+        }
+    }
+
+    Ok(rows)
+}
+
+// Color of sheet pixel (x, y), counted from the bottom left. Red and blue count the position
+// inside a cell, rising in even cells and falling in odd ones, green and alpha hold the cell
+// column and row
+pub fn sheet_color(x: u32, y: u32) -> [u8; 4] {
+    let (column, row) = (x / CELL_SIZE, y / CELL_SIZE);
+    let offset = |cell: u32, position: u32| {
+        if cell.is_multiple_of(2) {
+            position % CELL_SIZE
+        } else {
+            CELL_SIZE - position % CELL_SIZE
+        }
+    };
+    [
+        offset(column, x) as u8,
+        column as u8,
+        offset(row, y) as u8,
+        row as u8,
+    ]
+}
+
+fn check_sheet_size(width: u32, height: u32) -> anyhow::Result<()> {
+    if width == 0 || height == 0 {
+        anyhow::bail!("Sheet is empty");
+    }
+    if width > MAX_SHEET_SIZE || height > MAX_SHEET_SIZE {
+        anyhow::bail!(
+            "Sheet is {}x{}, the largest supported size is {}x{}",
+            width,
+            height,
+            MAX_SHEET_SIZE,
+            MAX_SHEET_SIZE
+        );
+    }
+    Ok(())
+}
+
+// Red or blue of a cell corner, cells alternate between rising and falling offsets
+fn edge(point: u32) -> u8 {
+    if point.is_multiple_of(2) {
+        0
+    } else {
+        CELL_SIZE as u8
+    }
+}
+
+// Directives that turn the 2x2 base into a `width` x `height` sheet of `sheet_color` pixels
+pub fn sheet_directives(width: u32, height: u32) -> anyhow::Result<String> {
+    check_sheet_size(width, height)?;
+    let columns = width.div_ceil(CELL_SIZE);
+    let rows = height.div_ceil(CELL_SIZE);
+    // One grid point per cell corner, each one a different color after the first scale
+    let (points_x, points_y) = (columns + 1, rows + 1);
+    let (scale_x, scale_y) = (points_x.next_power_of_two(), points_y.next_power_of_two());
+
+    let corner = |x: u32, y: u32| to_hex(&[x as u8, 1, y as u8, 0]);
+    let mut output = SHEET_BASE_DIRECTIVES.to_string();
+    output += &format!(
+        "?replace;{}={};{}={};{}={};{}={}",
+        to_hex(&SHEET_BASE_COLORS[0]),
+        corner(0, 0),
+        to_hex(&SHEET_BASE_COLORS[1]),
+        corner(scale_x, 0),
+        to_hex(&SHEET_BASE_COLORS[2]),
+        corner(0, scale_y),
+        to_hex(&SHEET_BASE_COLORS[3]),
+        corner(scale_x, scale_y),
+    );
+    output += &format!(
+        "?scalebilinear={};{}?crop;0;0;{};{}",
+        scale_x, scale_y, points_x, points_y
+    );
+
+    // Grid points become the cell corner colors, interpolating between them fills the cells
+    output += "?replace";
+    for y in 0..points_y {
+        for x in 0..points_x {
+            let corner_color = [edge(x), x as u8, edge(y), y as u8];
+            output += &format!(";{}={}", corner(x, y), to_hex(&corner_color));
+        }
+    }
+    output += &format!("?scalebilinear={}?crop;0;0;{};{}", CELL_SIZE, width, height);
+    Ok(output)
+}
+
+// Cell templates take their grid points from the frame layout, which the bundled base directives
+// build 43 pixels wide and high
+const CELL_LAYOUT_SIZE: u32 = 43;
+// Largest frame the cell layout can encode, one grid point per cell corner
+pub const MAX_CELL_FRAME_SIZE: u32 = CELL_SIZE * (CELL_LAYOUT_SIZE - 1);
+
+// Frame id -> first cell column and row of its block. Every id gets its own block of cells on
+// one sheet, left to right and then bottom to top
+pub fn cell_blocks(
+    frame_width: u32,
+    frame_height: u32,
+    ids: &BTreeSet<u8>,
+) -> anyhow::Result<HashMap<u8, (u32, u32)>> {
+    if frame_width > MAX_CELL_FRAME_SIZE || frame_height > MAX_CELL_FRAME_SIZE {
+        anyhow::bail!(
+            "Frame size {}x{} is too large for a cell template, the limit is {}x{}",
+            frame_width,
+            frame_height,
+            MAX_CELL_FRAME_SIZE,
+            MAX_CELL_FRAME_SIZE
+        );
+    }
+    let (columns, rows) = (
+        frame_width.div_ceil(CELL_SIZE),
+        frame_height.div_ceil(CELL_SIZE),
+    );
+    let (per_row, per_column) = (
+        MAX_SHEET_SIZE / CELL_SIZE / columns,
+        MAX_SHEET_SIZE / CELL_SIZE / rows,
+    );
+    if ids.len() as u32 > per_row * per_column {
+        anyhow::bail!(
+            "{} frame ids don't fit a cell template of {}x{} frames, the limit is {}",
+            ids.len(),
+            frame_width,
+            frame_height,
+            per_row * per_column
+        );
+    }
+    Ok(ids
+        .iter()
+        .enumerate()
+        .map(|(index, id)| {
+            let index = index as u32;
+            (*id, ((index % per_row) * columns, (index / per_row) * rows))
+        })
+        .collect())
+}
+
+// Directives that turn the [x, id, y, 0] frame layout into a `width` x `height` block of
+// `sheet_color` pixels for every frame id
+pub fn cell_directives(width: u32, height: u32, blocks: &HashMap<u8, (u32, u32)>) -> String {
+    let (points_x, points_y) = (
+        width.div_ceil(CELL_SIZE) + 1,
+        height.div_ceil(CELL_SIZE) + 1,
+    );
+    let mut output = format!("?crop;0;0;{};{}?replace", points_x, points_y);
+    let mut blocks: Vec<_> = blocks.iter().collect();
+    blocks.sort();
+    for (id, (column, row)) in blocks {
+        for y in 0..points_y {
+            for x in 0..points_x {
+                let (x_cell, y_cell) = (column + x, row + y);
+                let corner_color = [edge(x_cell), x_cell as u8, edge(y_cell), y_cell as u8];
+                output += &format!(
+                    ";{}={}",
+                    to_hex(&[x as u8, *id, y as u8, 0]),
+                    to_hex(&corner_color)
+                );
+            }
+        }
+    }
+    output += &format!("?scalebilinear={}?crop;0;0;{};{}", CELL_SIZE, width, height);
+    output
+}
+
+// Cell layout color of every frame pixel, rows top to bottom like `create`
+pub fn cell_colors(
+    frame_width: u32,
+    frame_height: u32,
+    frames: &[Vec<String>],
+    blocks: &HashMap<u8, (u32, u32)>,
+) -> anyhow::Result<Vec<Vec<Option<[u8; 4]>>>> {
+    let vertical_frames = frames.len();
+    let horizontal_frames = frames.first().map(Vec::len).unwrap_or(0);
+    let v = vertical_frames * frame_height as usize;
+    let mut rows = vec![vec![None; horizontal_frames * frame_width as usize]; v];
+
+    for (row, ids) in frames.iter().enumerate() {
+        for (column, id) in ids.iter().enumerate() {
+            if id.is_empty() {
+                continue;
+            }
+            let id = frame_id(id)?;
+            let Some((x_cell, y_cell)) = blocks.get(&id) else {
+                anyhow::bail!("Frame id '{:02x}' has no cell block", id);
+            };
+            for y in 0..frame_height {
+                for x in 0..frame_width {
+                    let color = sheet_color(x_cell * CELL_SIZE + x, y_cell * CELL_SIZE + y);
+                    // Rows count down from the top, frame y counts up from the bottom
+                    let top = row * frame_height as usize;
+                    rows[top + (frame_height - 1 - y) as usize]
+                        [column * frame_width as usize + x as usize] = Some(color);
                 }
             }
         }
     }
+    Ok(rows)
+}
+
+// Sheet color -> sprite color for every pixel of `img`
+pub fn sheet_palette(img: image::Image) -> anyhow::Result<HashMap<String, String>> {
+    check_sheet_size(img.weight(), img.height())?;
+    let mut palette = HashMap::new();
+    for y in 0..img.height() {
+        for x in 0..img.weight() {
+            let pixel = img.get_pixel(x, y)?;
+            let encoded = sheet_color(x, y);
+            // Cells above the first row have a visible alpha, so they need clearing too
+            if pixel[3] == 0 && encoded[3] == 0 {
+                continue;
+            }
+            let pixel = if pixel[3] == 0 { [0; 4] } else { pixel };
+            palette.insert(to_hex(&encoded), to_hex(&pixel));
+        }
+    }
+    Ok(palette)
+}
 
-    rows
+// Inverse of `sheet_color`, `None` for colors the layout never produces
+pub fn sheet_position(color: [u8; 4]) -> Option<(u32, u32)> {
+    let [r, column, b, row] = color.map(|c| c as u32);
+    let position = |cell: u32, offset: u32| {
+        let offset = if cell.is_multiple_of(2) {
+            offset
+        } else {
+            CELL_SIZE.checked_sub(offset)?
+        };
+        (offset < CELL_SIZE).then_some(cell * CELL_SIZE + offset)
+    };
+    Some((position(column, r)?, position(row, b)?))
 }